@@ -0,0 +1,472 @@
+// A stand-in for the Spotify Web API and accounts service, for testing spotify-cli (and
+// whatever is built on it) without a network or an account. Point the CLI at it with
+// SPOTIFY_CLI_API_URL=http://<addr>/v1 and SPOTIFY_CLI_ACCOUNTS_URL=http://<addr>.
+
+#[path = "../../http.rs"]
+mod http;
+mod response;
+mod store;
+
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use clap::Parser;
+use ureq::serde_json::{self, Value};
+
+use http::Request;
+use response::Response;
+use store::{Fixture, Playlist, Store};
+
+const DEFAULT_FIXTURE: &str = include_str!("fixture.json");
+
+/// Scopes handed out with every user token, everything spotify-cli might ask for
+const SCOPES: &str = "playlist-read-private playlist-modify-public playlist-modify-private user-top-read user-library-read";
+
+#[derive(Parser)]
+#[command(about = "A mock Spotify Web API for testing spotify-cli offline")]
+struct Args {
+    /// Address to listen on, port 0 picks a free one
+    #[arg(short, long, default_value = "127.0.0.1:9090")]
+    bind: String,
+
+    /// JSON fixture to start from [default: a small built-in one]
+    #[arg(short, long, value_name = "FILE")]
+    fixture: Option<PathBuf>,
+
+    /// Answer every Nth API request with a 429
+    #[arg(long, value_name = "N")]
+    rate_limit_every: Option<u64>,
+
+    /// Seconds to ask for in Retry-After with those 429s
+    #[arg(long, value_name = "SECONDS", default_value_t = 1)]
+    retry_after: u64,
+}
+
+struct Server {
+    store: Mutex<Store>,
+    rate_limit_every: Option<u64>,
+    retry_after: u64,
+    /// API requests so far, for the rate limiting
+    requests: AtomicU64,
+    /// Tokens handed out so far, so each one is different
+    tokens: AtomicU64,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let fixture = match &args.fixture {
+        Some(path) => std::fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => DEFAULT_FIXTURE.to_owned(),
+    };
+    let server = Server::new(&fixture, args.rate_limit_every, args.retry_after).unwrap_or_else(|e| {
+        eprintln!("Bad fixture: {}", e);
+        std::process::exit(1);
+    });
+
+    let listener = TcpListener::bind(&args.bind).unwrap_or_else(|e| {
+        eprintln!("Failed to bind to {}: {}", args.bind, e);
+        std::process::exit(1);
+    });
+    let addr = listener.local_addr().unwrap();
+    // Scripts can read the address from here when binding to port 0
+    println!("Listening on http://{}", addr);
+    println!("SPOTIFY_CLI_API_URL=http://{}/v1", addr);
+    println!("SPOTIFY_CLI_ACCOUNTS_URL=http://{}", addr);
+    std::io::stdout().flush().ok();
+
+    serve(listener, Arc::new(server));
+}
+
+fn serve(listener: TcpListener, server: Arc<Server>) {
+    for stream in listener.incoming().flatten() {
+        let server = server.clone();
+        std::thread::spawn(move || server.answer(stream));
+    }
+}
+
+impl Server {
+    fn new(fixture: &str, rate_limit_every: Option<u64>, retry_after: u64) -> Result<Server, String> {
+        let fixture: Fixture = serde_json::from_str(fixture).map_err(|e| e.to_string())?;
+        Ok(Server {
+            store: Mutex::new(Store::new(fixture)?),
+            rate_limit_every,
+            retry_after,
+            requests: AtomicU64::new(0),
+            tokens: AtomicU64::new(0),
+        })
+    }
+
+    fn answer(&self, stream: TcpStream) {
+        let response = match Request::read(&mut BufReader::new(&stream)) {
+            Ok(request) => self.handle(&request),
+            Err(e) => Response::error(400, &e.to_string()),
+        };
+        response.write_to(&stream).ok();
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        if request.path == "/api/token" {
+            return match request.method.as_str() {
+                "POST" => self.token(request),
+                _ => Response::error(405, "Method not allowed"),
+            };
+        }
+        let Some(path) = request.path.strip_prefix("/v1/") else {
+            return Response::error(404, "Service not found");
+        };
+
+        let count = self.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.rate_limit_every.is_some_and(|every| every > 0 && count % every == 0) {
+            let mut response = Response::error(429, "API rate limit exceeded");
+            response.headers.push(("Retry-After", self.retry_after.to_string()));
+            return response;
+        }
+        let has_token = request
+            .header("Authorization")
+            .and_then(|auth| auth.strip_prefix("Bearer "))
+            .is_some_and(|token| !token.trim().is_empty());
+        if !has_token {
+            return Response::error(401, "No token provided");
+        }
+
+        let segments: Vec<&str> = path.split('/').collect();
+        let mut store = self.store.lock().unwrap();
+        let res = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["me"]) => Ok(Response::json(200, store.user())),
+            ("GET", ["me", "top", "tracks"]) => page(request, store.top_tracks(), 20, 50),
+            ("GET", ["me", "playlists"]) => page(request, store.user_playlists(), 20, 50),
+            ("POST", ["me", "playlists"]) => create_playlist(&mut store, request),
+            ("POST", ["users", user, "playlists"]) if *user == store.user_id => create_playlist(&mut store, request),
+            ("POST", ["users", _, "playlists"]) => Err(Response::error(403, "You cannot create a playlist for another user")),
+            ("GET", ["playlists", id]) => get_playlist(&store, request, id),
+            ("GET", ["playlists", id, "tracks"]) => playlist(&store, id).and_then(|playlist| {
+                let items = playlist.items.iter().map(|item| store.item(item)).collect();
+                page(request, items, 100, 100)
+            }),
+            ("POST", ["playlists", id, "tracks"]) => add_items(&mut store, request, id),
+            ("PUT", ["playlists", id, "tracks"]) => replace_or_reorder(&mut store, request, id),
+            (_, ["me"] | ["me", "top", "tracks"] | ["me", "playlists"] | ["users", _, "playlists"])
+            | (_, ["playlists", _] | ["playlists", _, "tracks"]) => Err(Response::error(405, "Method not allowed")),
+            _ => Err(Response::error(404, "Service not found")),
+        };
+        res.unwrap_or_else(|response| response)
+    }
+
+    /// The accounts service token endpoint, handing out made up tokens for any client
+    fn token(&self, request: &Request) -> Response {
+        let form: HashMap<String, String> = request.form().into_iter().collect();
+        let field = |name: &str| form.get(name).map(String::as_str).filter(|v| !v.is_empty());
+        let basic = request.header("Authorization").is_some_and(|auth| auth.starts_with("Basic "));
+        if field("client_id").is_none() && !basic {
+            return Response::oauth_error("invalid_client", "Invalid client");
+        }
+
+        let n = self.tokens.fetch_add(1, Ordering::SeqCst) + 1;
+        let access_token = format!("mock-access-token-{}", n);
+        match field("grant_type") {
+            Some("authorization_code") if field("code").is_some() => Response::json(
+                200,
+                &ureq::json!({
+                    "access_token": access_token,
+                    "token_type": "Bearer",
+                    "expires_in": 3600,
+                    "refresh_token": format!("mock-refresh-token-{}", n),
+                    "scope": SCOPES,
+                }),
+            ),
+            Some("refresh_token") if field("refresh_token").is_some() => Response::json(
+                200,
+                &ureq::json!({
+                    "access_token": access_token,
+                    "token_type": "Bearer",
+                    "expires_in": 3600,
+                    "scope": SCOPES,
+                }),
+            ),
+            Some("client_credentials") if field("client_secret").is_some() || basic => Response::json(
+                200,
+                &ureq::json!({"access_token": access_token, "token_type": "Bearer", "expires_in": 3600}),
+            ),
+            Some("authorization_code" | "refresh_token" | "client_credentials") => {
+                Response::oauth_error("invalid_request", "Missing parameters for this grant type")
+            }
+            _ => Response::oauth_error("unsupported_grant_type", "grant_type must be client_credentials, authorization_code or refresh_token"),
+        }
+    }
+}
+
+/// A paging object over `items`, honouring limit and offset and linking to the next page
+fn page(request: &Request, items: Vec<Value>, default_limit: usize, max_limit: usize) -> Result<Response, Response> {
+    let number = |name: &str, default: usize| match request.query(name) {
+        None => Ok(default),
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| Response::error(400, &format!("Invalid {}", name))),
+    };
+    let limit = number("limit", default_limit)?;
+    let offset = number("offset", 0)?;
+    if limit == 0 || limit > max_limit {
+        return Err(Response::error(400, "Invalid limit"));
+    }
+
+    let host = request.header("Host").unwrap_or("localhost");
+    let link = |offset: usize| {
+        let mut query = request.query_without(&["offset", "limit"]);
+        query.push(("offset".to_owned(), offset.to_string()));
+        query.push(("limit".to_owned(), limit.to_string()));
+        let query: Vec<String> = query.iter().map(|(n, v)| format!("{}={}", http::percent_encode(n), http::percent_encode(v))).collect();
+        format!("http://{}{}?{}", host, request.path, query.join("&"))
+    };
+
+    let total = items.len();
+    let next = (offset + limit < total).then(|| link(offset + limit));
+    let previous = (offset > 0).then(|| link(offset.saturating_sub(limit)));
+    let items: Vec<Value> = items.into_iter().skip(offset).take(limit).collect();
+    Ok(Response::json(
+        200,
+        &ureq::json!({
+            "href": link(offset),
+            "items": items,
+            "limit": limit,
+            "next": next,
+            "offset": offset,
+            "previous": previous,
+            "total": total,
+        }),
+    ))
+}
+
+fn json_body(request: &Request) -> Result<Value, Response> {
+    request.json().ok_or_else(|| Response::error(400, "Error parsing JSON."))
+}
+
+fn playlist<'a>(store: &'a Store, id: &str) -> Result<&'a Playlist, Response> {
+    store.playlist(id).ok_or_else(|| Response::error(404, "Resource not found"))
+}
+
+/// Looks up a playlist the user may change
+fn own_playlist<'a>(store: &'a mut Store, id: &str) -> Result<&'a mut Playlist, Response> {
+    let user_id = store.user_id.clone();
+    let playlist = store.playlist_mut(id).ok_or_else(|| Response::error(404, "Resource not found"))?;
+    match playlist.is_owned_by(&user_id) {
+        true => Ok(playlist),
+        false => Err(Response::error(403, "You cannot modify a playlist you don't own")),
+    }
+}
+
+fn get_playlist(store: &Store, request: &Request, id: &str) -> Result<Response, Response> {
+    let playlist = playlist(store, id)?;
+    let mut body = playlist.simplified();
+    let items = playlist.items.iter().map(|item| store.item(item)).collect();
+    let tracks = page(request, items, 100, 100)?;
+    body["tracks"] = serde_json::from_str(&tracks.body).unwrap();
+    Ok(Response::json(200, &body))
+}
+
+fn create_playlist(store: &mut Store, request: &Request) -> Result<Response, Response> {
+    let body = json_body(request)?;
+    let name = body["name"].as_str().ok_or_else(|| Response::error(400, "Missing required field: name"))?;
+    Ok(Response::json(201, &store.create_playlist(name).simplified()))
+}
+
+/// `uris` come from the body, or the query as a comma separated list
+fn uris(request: &Request, body: &Value) -> Result<Vec<String>, Response> {
+    let uris = match (&body["uris"], request.query("uris")) {
+        (Value::Array(uris), _) => uris
+            .iter()
+            .map(|uri| uri.as_str().map(str::to_owned))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Response::error(400, "uris must be strings"))?,
+        (_, Some(uris)) => uris.split(',').filter(|u| !u.is_empty()).map(str::to_owned).collect(),
+        _ => return Err(Response::error(400, "No uris provided")),
+    };
+    store::check_uris(&uris).map_err(|message| Response::error(400, &message))?;
+    Ok(uris)
+}
+
+fn snapshot(playlist: &Playlist, status: u16) -> Result<Response, Response> {
+    Ok(Response::json(status, &ureq::json!({"snapshot_id": playlist.snapshot_id()})))
+}
+
+fn add_items(store: &mut Store, request: &Request, id: &str) -> Result<Response, Response> {
+    let body = json_body(request)?;
+    let uris = uris(request, &body)?;
+    let position = match (&body["position"], request.query("position")) {
+        (Value::Number(n), _) => n.as_u64().map(|n| n as usize),
+        (_, Some(n)) => n.parse().ok(),
+        _ => None,
+    };
+
+    let user_id = store.user_id.clone();
+    let playlist = own_playlist(store, id)?;
+    let position = position.unwrap_or(playlist.items.len());
+    if position > playlist.items.len() {
+        return Err(Response::error(400, "Index out of bounds"));
+    }
+    let items = Playlist::new_items(&uris, &user_id);
+    playlist.items.splice(position..position, items);
+    playlist.changed();
+    snapshot(playlist, 201)
+}
+
+/// PUT does two different things: replace everything with `uris`, or move a range of items
+fn replace_or_reorder(store: &mut Store, request: &Request, id: &str) -> Result<Response, Response> {
+    let body = json_body(request)?;
+    let user_id = store.user_id.clone();
+
+    if body.get("range_start").is_none() {
+        let uris = uris(request, &body)?;
+        let playlist = own_playlist(store, id)?;
+        playlist.items = Playlist::new_items(&uris, &user_id);
+        playlist.changed();
+        return snapshot(playlist, 200);
+    }
+
+    let field = |name: &str| body[name].as_u64().map(|n| n as usize);
+    let (Some(start), Some(before)) = (field("range_start"), field("insert_before")) else {
+        return Err(Response::error(400, "range_start and insert_before must be numbers"));
+    };
+    let length = field("range_length").unwrap_or(1);
+    let playlist = own_playlist(store, id)?;
+    let len = playlist.items.len();
+    if start + length > len || before > len {
+        return Err(Response::error(400, "Index out of bounds"));
+    }
+
+    if !(start..=start + length).contains(&before) {
+        let moved: Vec<_> = playlist.items.drain(start..start + length).collect();
+        let at = if before > start { before - length } else { before };
+        playlist.items.splice(at..at, moved);
+    }
+    playlist.changed();
+    snapshot(playlist, 200)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a server on a free port, returning the base URL
+    fn start(rate_limit_every: Option<u64>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = Server::new(DEFAULT_FIXTURE, rate_limit_every, 0).unwrap();
+        std::thread::spawn(move || serve(listener, Arc::new(server)));
+        url
+    }
+
+    type Res = Result<Value, Box<ureq::Error>>;
+
+    fn get(url: &str) -> Res {
+        let response = ureq::get(url).set("Authorization", "Bearer x").call()?;
+        Ok(response.into_json().unwrap())
+    }
+
+    fn send(method: &str, url: &str, body: Value) -> Res {
+        let response = ureq::request(method, url).set("Authorization", "Bearer x").send_json(body)?;
+        Ok(response.into_json().unwrap())
+    }
+
+    fn status(res: Res) -> u16 {
+        match res.map_err(|e| *e) {
+            Ok(_) => 200,
+            Err(ureq::Error::Status(code, _)) => code,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn uris(from: usize, to: usize) -> Vec<String> {
+        (from..to).map(|n| format!("spotify:track:mock{:018}", n)).collect()
+    }
+
+    fn item_uris(url: &str) -> Vec<String> {
+        let mut uris = Vec::new();
+        let mut next = Some(format!("{}?limit=50", url));
+        while let Some(url) = next {
+            let page = get(&url).unwrap();
+            for item in page["items"].as_array().unwrap() {
+                uris.push(item["track"]["uri"].as_str().unwrap().to_owned());
+            }
+            next = page["next"].as_str().map(str::to_owned);
+        }
+        uris
+    }
+
+    #[test]
+    fn test_token_and_me() {
+        let url = start(None);
+        let token: Value = ureq::post(&format!("{}/api/token", url))
+            .send_form(&[("grant_type", "refresh_token"), ("refresh_token", "r"), ("client_id", "c")])
+            .unwrap()
+            .into_json()
+            .unwrap();
+        assert_eq!(token["expires_in"], 3600);
+
+        let res = ureq::post(&format!("{}/api/token", url)).send_form(&[("grant_type", "refresh_token")]);
+        assert!(matches!(res, Err(ureq::Error::Status(400, _))));
+
+        assert_eq!(get(&format!("{}/v1/me", url)).unwrap()["id"], "mock-user");
+        let res = ureq::get(&format!("{}/v1/me", url)).call();
+        assert!(matches!(res, Err(ureq::Error::Status(401, _))));
+    }
+
+    #[test]
+    fn test_playlist_writes() {
+        let url = start(None);
+        let playlist = send("POST", &format!("{}/v1/users/mock-user/playlists", url), ureq::json!({"name": "New"})).unwrap();
+        let tracks = format!("{}/v1/playlists/{}/tracks", url, playlist["id"].as_str().unwrap());
+        let mut snapshots = vec![playlist["snapshot_id"].clone()];
+
+        // The 100 item limit, then paging over what did get in
+        assert_eq!(status(send("PUT", &tracks, ureq::json!({"uris": uris(0, 101)}))), 400);
+        for (from, to) in [(0, 100), (100, 130)] {
+            let res = send("POST", &tracks, ureq::json!({"uris": uris(from, to)})).unwrap();
+            snapshots.push(res["snapshot_id"].clone());
+        }
+        assert_eq!(item_uris(&tracks), uris(0, 130));
+
+        // Position, replacing and reordering
+        let res = send("POST", &tracks, ureq::json!({"uris": uris(200, 201), "position": 0})).unwrap();
+        snapshots.push(res["snapshot_id"].clone());
+        assert_eq!(item_uris(&tracks)[..2], [uris(200, 201), uris(0, 1)].concat());
+
+        let res = send("PUT", &tracks, ureq::json!({"uris": uris(0, 3)})).unwrap();
+        snapshots.push(res["snapshot_id"].clone());
+        let res = send("PUT", &tracks, ureq::json!({"range_start": 0, "insert_before": 3})).unwrap();
+        snapshots.push(res["snapshot_id"].clone());
+        let expected = [uris(1, 3), uris(0, 1)].concat();
+        assert_eq!(item_uris(&tracks), expected);
+        let res = send("PUT", &tracks, ureq::json!({"range_start": 1, "insert_before": 0, "range_length": 2})).unwrap();
+        snapshots.push(res["snapshot_id"].clone());
+        assert_eq!(item_uris(&tracks), [uris(2, 3), uris(0, 1), uris(1, 2)].concat());
+
+        // Every write made a new snapshot
+        let count = snapshots.len();
+        snapshots.sort_by_key(|s| s.to_string());
+        snapshots.dedup();
+        assert_eq!(snapshots.len(), count);
+
+        assert_eq!(status(send("POST", &tracks, ureq::json!({"uris": ["nonsense"]}))), 400);
+        assert_eq!(status(get(&format!("{}/v1/playlists/nope/tracks", url))), 404);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let url = start(Some(2));
+        let me = format!("{}/v1/me", url);
+        assert_eq!(status(get(&me)), 200);
+        match ureq::get(&me).set("Authorization", "Bearer x").call() {
+            Err(ureq::Error::Status(429, response)) => assert_eq!(response.header("Retry-After"), Some("0")),
+            _ => panic!("expected a 429"),
+        }
+        assert_eq!(status(get(&me)), 200);
+    }
+}
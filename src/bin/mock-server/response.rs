@@ -0,0 +1,55 @@
+// What the mock server answers with, in the shapes the Web API and accounts service use.
+
+use std::io::{self, Write};
+use std::net::TcpStream;
+
+use ureq::serde_json::Value;
+
+use crate::http;
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn json(status: u16, body: &Value) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type", "application/json; charset=utf-8".to_owned())],
+            body: body.to_string(),
+        }
+    }
+
+    /// What the Web API sends for errors
+    pub fn error(status: u16, message: &str) -> Response {
+        Response::json(status, &ureq::json!({"error": {"status": status, "message": message}}))
+    }
+
+    /// What the accounts service sends for errors
+    pub fn oauth_error(error: &str, description: &str) -> Response {
+        Response::json(400, &ureq::json!({"error": error, "error_description": description}))
+    }
+
+    pub fn write_to(&self, mut stream: &TcpStream) -> io::Result<()> {
+        let status = format!("{} {}", self.status, reason(self.status));
+        let headers: Vec<(&str, &str)> = self.headers.iter().map(|(name, value)| (*name, value.as_str())).collect();
+        stream.write_all(http::response(&status, &headers, &self.body).as_bytes())?;
+        stream.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        429 => "Too Many Requests",
+        _ => "Whatever",
+    }
+}
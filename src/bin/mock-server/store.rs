@@ -0,0 +1,308 @@
+// Everything the mock server knows, seeded from a fixture and changed by the requests it gets.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use serde::Deserialize;
+use ureq::serde_json::Value;
+
+/// Playlist writes over this many items get a 400, as from Spotify
+pub const MAX_WRITE: usize = 100;
+
+/// When fixture playlist items say nothing else
+const FIXTURE_ADDED_AT: &str = "2024-01-01T00:00:00Z";
+
+#[derive(Deserialize)]
+pub struct Fixture {
+    /// A user object, needs at least an "id"
+    user: Value,
+    /// Track objects, each needs at least a "uri". Any other track URI is made up on the spot.
+    #[serde(default)]
+    tracks: Vec<Value>,
+    /// Track URIs, most played first
+    #[serde(default)]
+    top_tracks: Vec<String>,
+    #[serde(default)]
+    playlists: Vec<FixturePlaylist>,
+}
+
+#[derive(Deserialize)]
+struct FixturePlaylist {
+    id: String,
+    name: String,
+    /// Owner's user ID, the fixture user if missing
+    owner: Option<String>,
+    /// Track, episode or local file URIs, null for a track that was removed from Spotify
+    #[serde(default)]
+    tracks: Vec<Option<String>>,
+}
+
+pub struct Store {
+    pub user_id: String,
+    user: Value,
+    tracks: HashMap<String, Value>,
+    top_tracks: Vec<String>,
+    /// In the order they were created
+    playlists: Vec<Playlist>,
+    /// For made up IDs
+    next_id: u64,
+}
+
+pub struct Playlist {
+    pub id: String,
+    name: String,
+    owner: String,
+    /// Bumped by every write, and the snapshot ID is made from it
+    version: u64,
+    pub items: Vec<Item>,
+}
+
+#[derive(Clone)]
+pub struct Item {
+    /// None once removed from Spotify
+    uri: Option<String>,
+    added_at: String,
+    added_by: String,
+}
+
+impl Store {
+    pub fn new(fixture: Fixture) -> Result<Store, String> {
+        let user_id = fixture.user["id"].as_str().ok_or("The fixture user needs an id")?.to_owned();
+
+        let mut tracks = HashMap::new();
+        for track in fixture.tracks {
+            let uri = track["uri"].as_str().ok_or("Every fixture track needs a uri")?.to_owned();
+            tracks.insert(uri, track);
+        }
+
+        let playlists = fixture
+            .playlists
+            .into_iter()
+            .map(|playlist| {
+                let owner = playlist.owner.unwrap_or_else(|| user_id.clone());
+                Playlist {
+                    items: playlist
+                        .tracks
+                        .into_iter()
+                        .map(|uri| Item {
+                            uri,
+                            added_at: FIXTURE_ADDED_AT.to_owned(),
+                            added_by: owner.clone(),
+                        })
+                        .collect(),
+                    id: playlist.id,
+                    name: playlist.name,
+                    owner,
+                    version: 1,
+                }
+            })
+            .collect();
+
+        Ok(Store {
+            user_id,
+            user: fixture.user,
+            tracks,
+            top_tracks: fixture.top_tracks,
+            playlists,
+            next_id: 0,
+        })
+    }
+
+    pub fn user(&self) -> &Value {
+        &self.user
+    }
+
+    pub fn top_tracks(&self) -> Vec<Value> {
+        self.top_tracks.iter().map(|uri| self.track(uri)).collect()
+    }
+
+    /// The fixture's track, or one made up from the URI. Episode URIs get an episode, and
+    /// local files look like Spotify's: everything but the name and URI is null.
+    fn track(&self, uri: &str) -> Value {
+        if let Some(track) = self.tracks.get(uri) {
+            return track.clone();
+        }
+        if let Some(local) = uri.strip_prefix("spotify:local:") {
+            // Artist, album, title and duration, with '+' for spaces
+            let parts: Vec<String> = local.split(':').map(|part| part.replace('+', " ")).collect();
+            let part = |n: usize| parts.get(n).cloned().unwrap_or_default();
+            return ureq::json!({
+                "id": null,
+                "name": part(2),
+                "uri": uri,
+                "href": null,
+                "type": "track",
+                "is_local": true,
+                "track_number": 0,
+                "artists": [{"id": null, "href": null, "name": part(0)}],
+                "album": {"id": null, "href": null, "name": part(1), "release_date": null},
+            });
+        }
+        let id = uri.rsplit(':').next().unwrap_or(uri);
+        if uri.starts_with("spotify:episode:") {
+            return ureq::json!({
+                "id": id,
+                "name": format!("Episode {}", id),
+                "uri": uri,
+                "href": format!("https://api.spotify.com/v1/episodes/{}", id),
+                "type": "episode",
+                "release_date": FIXTURE_ADDED_AT[..10],
+                "show": {"id": "mockshow00000000000001", "name": "Mock Show"},
+            });
+        }
+        ureq::json!({
+            "type": "track",
+            "id": id,
+            "name": format!("Track {}", id),
+            "uri": uri,
+            "href": format!("https://api.spotify.com/v1/tracks/{}", id),
+            "track_number": 1,
+            "artists": [],
+            "album": null,
+        })
+    }
+
+    pub fn playlist(&self, id: &str) -> Option<&Playlist> {
+        self.playlists.iter().find(|p| p.id == id)
+    }
+
+    pub fn playlist_mut(&mut self, id: &str) -> Option<&mut Playlist> {
+        self.playlists.iter_mut().find(|p| p.id == id)
+    }
+
+    /// The user's playlists, newest first
+    pub fn user_playlists(&self) -> Vec<Value> {
+        self.playlists.iter().rev().map(Playlist::simplified).collect()
+    }
+
+    pub fn create_playlist(&mut self, name: &str) -> &Playlist {
+        self.next_id += 1;
+        // Real IDs are 22 characters of base62, which some commands check for
+        let id = format!("mock{:018}", self.next_id);
+        self.playlists.push(Playlist {
+            id,
+            name: name.to_owned(),
+            owner: self.user_id.clone(),
+            version: 1,
+            items: Vec::new(),
+        });
+        self.playlists.last().unwrap()
+    }
+
+    pub fn item(&self, item: &Item) -> Value {
+        ureq::json!({
+            "added_at": item.added_at,
+            "added_by": user_ref(&item.added_by),
+            "is_local": item.uri.as_ref().is_some_and(|uri| uri.starts_with("spotify:local:")),
+            "track": item.uri.as_ref().map(|uri| self.track(uri)),
+        })
+    }
+}
+
+impl Playlist {
+    pub fn snapshot_id(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(format!("{},{}", self.version, self.id))
+    }
+
+    pub fn simplified(&self) -> Value {
+        ureq::json!({
+            "id": self.id,
+            "name": self.name,
+            "uri": format!("spotify:playlist:{}", self.id),
+            "href": format!("https://api.spotify.com/v1/playlists/{}", self.id),
+            "external_urls": {"spotify": format!("https://open.spotify.com/playlist/{}", self.id)},
+            "owner": user_ref(&self.owner),
+            "snapshot_id": self.snapshot_id(),
+            "tracks": {"total": self.items.len()},
+        })
+    }
+
+    /// New items for `uris`, added now by `user`
+    pub fn new_items(uris: &[String], user: &str) -> Vec<Item> {
+        let added_at = timestamp(SystemTime::now());
+        uris.iter()
+            .map(|uri| Item {
+                uri: Some(uri.clone()),
+                added_at: added_at.clone(),
+                added_by: user.to_owned(),
+            })
+            .collect()
+    }
+
+    pub fn is_owned_by(&self, user: &str) -> bool {
+        self.owner == user
+    }
+
+    pub fn changed(&mut self) {
+        self.version += 1;
+    }
+}
+
+fn user_ref(id: &str) -> Value {
+    ureq::json!({
+        "id": id,
+        "type": "user",
+        "uri": format!("spotify:user:{}", id),
+        "href": format!("https://api.spotify.com/v1/users/{}", id),
+    })
+}
+
+/// RFC 3339 in UTC, the way Spotify writes added_at
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rest) = (secs / 86400, secs % 86400);
+
+    // Days to a civil date, from Howard Hinnant's date algorithms
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Checks a list of URIs to be written, the way Spotify would. Local files can be in
+/// a fixture, but like with Spotify they can't be added.
+pub fn check_uris(uris: &[String]) -> Result<(), String> {
+    if uris.len() > MAX_WRITE {
+        return Err(format!("You can add a maximum of {} tracks per request.", MAX_WRITE));
+    }
+    match uris.iter().find(|uri| !is_valid_uri(uri)) {
+        Some(uri) => Err(format!("Invalid base62 id: {}", uri)),
+        None => Ok(()),
+    }
+}
+
+fn is_valid_uri(uri: &str) -> bool {
+    let mut parts = uri.split(':');
+    matches!(
+        (parts.next(), parts.next(), parts.next(), parts.next()),
+        (Some("spotify"), Some("track" | "episode"), Some(id), None) if !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1709210096)), "2024-02-29T12:34:56Z");
+    }
+}
@@ -0,0 +1,131 @@
+use crate::spotify::playlist::parse_playlist_id;
+use crate::util;
+
+/// What a playlist argument points at: an alias from the config, or an ID, URI or link.
+/// Anything else is passed along as is and Spotify gets to complain about it.
+pub fn resolve(path: &util::ConfigPath, playlist: &str) -> String {
+    let config = util::read_config(path).unwrap_or_else(|e| e.exit());
+    resolve_in(&config, playlist)
+}
+
+fn resolve_in(config: &util::Config, playlist: &str) -> String {
+    if let Some(id) = config.aliases.get(playlist) {
+        return id.clone();
+    }
+    parse_playlist_id(playlist).unwrap_or(playlist).to_owned()
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains([':', '/']) && !name.contains(char::is_whitespace)
+}
+
+/// Adds or replaces an alias. `playlist` can be an ID, URI or link, another alias,
+/// or the name of one of the user's playlists.
+pub fn add(path: util::ConfigPath, name: &str, playlist: &str) {
+    if !is_valid_name(name) {
+        eprintln!("Alias names can't be empty or contain ':', '/' or whitespace.");
+        std::process::exit(1);
+    }
+    if parse_playlist_id(name).is_some() {
+        eprintln!("{} looks like a playlist ID, pick another name.", name);
+        std::process::exit(1);
+    }
+
+    let mut config = util::read_config(&path).unwrap_or_else(|e| e.exit());
+    let id = match parse_playlist_id(playlist) {
+        Some(id) => id.to_owned(),
+        None => match config.aliases.get(playlist) {
+            Some(id) => id.clone(),
+            None => find_by_name(&path, playlist),
+        },
+    };
+
+    match config.aliases.insert(name.to_owned(), id.clone()) {
+        Some(old) if old != id => println!("Replaced {} (was {}).", name, old),
+        _ => {}
+    }
+    util::write_config(&path, config).unwrap_or_else(|e| e.exit());
+
+    println!("{} -> {}", name, id);
+}
+
+fn find_by_name(path: &util::ConfigPath, name: &str) -> String {
+    let client = util::get_client(path, &[]);
+    let playlists = match client.get_user_playlists() {
+        Ok(playlists) => playlists,
+        Err(e) => e.exit("Failed to get your playlists"),
+    };
+
+    // Exact matches win, otherwise be forgiving about case
+    let mut matches: Vec<_> = playlists.iter().filter(|p| p.name == name).collect();
+    if matches.is_empty() {
+        matches = playlists.iter().filter(|p| p.name.eq_ignore_ascii_case(name)).collect();
+    }
+    match matches.as_slice() {
+        [playlist] => playlist.id.clone(),
+        [] => {
+            eprintln!("{} isn't a playlist ID, URI, link or alias, and none of your playlists is called that.", name);
+            std::process::exit(1);
+        }
+        _ => {
+            eprintln!("More than one of your playlists is called {}, use its link instead:", name);
+            for playlist in matches {
+                eprintln!("  https://open.spotify.com/playlist/{}", playlist.id);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+pub fn remove(path: util::ConfigPath, name: &str) {
+    let mut config = util::read_config(&path).unwrap_or_else(|e| e.exit());
+    if config.aliases.remove(name).is_none() {
+        eprintln!("There's no alias called {}.", name);
+        std::process::exit(1);
+    }
+    util::write_config(&path, config).unwrap_or_else(|e| e.exit());
+
+    println!("Removed {}.", name);
+}
+
+pub fn list(path: util::ConfigPath) {
+    let config = util::read_config(&path).unwrap_or_else(|e| e.exit());
+    if config.aliases.is_empty() {
+        println!("No aliases, add one with `alias add <name> <playlist>`.");
+        return;
+    }
+
+    let width = config.aliases.keys().map(|name| name.len()).max().unwrap_or(0);
+    for (name, id) in &config.aliases {
+        println!("{:width$}  {}", name, id, width = width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let config: util::Config = toml::from_str(
+            r#"
+            [aliases]
+            taylor = "37i9dQZF1DXcBWIGoYBM5M"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(resolve_in(&config, "taylor"), "37i9dQZF1DXcBWIGoYBM5M");
+        assert_eq!(
+            resolve_in(&config, "https://open.spotify.com/playlist/5ABHKGoOzxkaa28ttQV9sE?si=x"),
+            "5ABHKGoOzxkaa28ttQV9sE"
+        );
+        assert_eq!(resolve_in(&config, "spotify:playlist:5ABHKGoOzxkaa28ttQV9sE"), "5ABHKGoOzxkaa28ttQV9sE");
+        assert_eq!(resolve_in(&config, "unknown"), "unknown");
+
+        assert!(is_valid_name("taylor"));
+        assert!(!is_valid_name("a b"));
+        assert!(!is_valid_name("spotify:x"));
+        assert!(!is_valid_name(""));
+    }
+}
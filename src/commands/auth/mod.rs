@@ -0,0 +1,390 @@
+mod pkce;
+mod redirect;
+
+use clap::Args;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use std::net::TcpListener;
+
+use crate::http;
+use crate::spotify;
+use crate::util::{self, Config};
+use pkce::Pkce;
+
+/// Always requested, `extra_scopes` get added on top
+pub const DEFAULT_SCOPES: &[&str] = &[
+    "user-top-read",
+    "playlist-modify-public",
+    "playlist-modify-private",
+];
+/// Needed by anything that writes to a playlist
+pub const SCOPES_PLAYLIST_MODIFY: &[&str] = &["playlist-modify-public", "playlist-modify-private"];
+
+const DEFAULT_REDIRECT_URI: &str = "http://localhost:8888";
+const ACCOUNTS_URL: &str = "https://accounts.spotify.com";
+
+#[derive(Args)]
+pub struct AuthArgs {
+    /// Authorize with PKCE, no client secret is needed or stored
+    #[arg(long)]
+    pub pkce: bool,
+
+    /// Redirect URI registered for the app [default: http://localhost:8888]
+    #[arg(long, value_name = "URI")]
+    pub redirect_uri: Option<String>,
+
+    /// Address the redirect listener binds to [default: taken from the redirect URI]
+    #[arg(long = "bind", value_name = "ADDRESS")]
+    pub bind_address: Option<String>,
+
+    /// Extra scope to request, may be given more than once
+    #[arg(long = "scope", value_name = "SCOPE")]
+    pub scopes: Vec<String>,
+
+    /// Don't listen for the redirect, read the redirected URL (or the code) from stdin instead
+    #[arg(long)]
+    pub manual: bool,
+
+    /// Reauthorize even if the profile already has a token
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[non_exhaustive]
+struct AuthorizationRequestResponse {
+    pub access_token: String,
+    pub expires_in: u64,
+    pub refresh_token: String,
+    pub scope: Option<String>,
+}
+
+pub fn run(path: util::ConfigPath, args: &AuthArgs) {
+    let use_pkce = args.pkce;
+    // Try to read our config, die if it isn't there!
+    let mut config = util::read_config(&path).unwrap_or_else(|e| e.exit());
+    if config.client_id.is_none() {
+        util::ConfigError::invalid(&path, "Cannot authorize! No client_id (set one with `config set client_id ...`).").exit();
+    }
+    if !use_pkce && config.client_secret.is_none() {
+        util::ConfigError::invalid(&path, "Cannot authorize! No client_secret (pass --pkce to authorize without one).").exit();
+    }
+
+    if config.refresh_token.is_some() && !args.force {
+        println!("Profile already contains a token.\nRun this command again with --force to reauthorize.");
+        return;
+    }
+
+    // Flags win over the config, and get remembered for next time
+    if args.redirect_uri.is_some() {
+        config.redirect_uri = args.redirect_uri.clone();
+    }
+    if args.bind_address.is_some() {
+        config.bind_address = args.bind_address.clone();
+    }
+    if !args.scopes.is_empty() {
+        let extra = config.extra_scopes.get_or_insert_with(Vec::new);
+        for scope in &args.scopes {
+            if !extra.contains(scope) {
+                extra.push(scope.to_owned());
+            }
+        }
+    }
+
+    let redirect_uri = config
+        .redirect_uri
+        .clone()
+        .unwrap_or(DEFAULT_REDIRECT_URI.to_owned());
+    let (authority, callback_path) = match redirect::split_uri(&redirect_uri) {
+        Some(parts) => parts,
+        None => {
+            let message = format!("Redirect URI {} should look like http://host:port/path", redirect_uri);
+            util::ConfigError::invalid(&path, message).exit();
+        }
+    };
+    let bind_address = config
+        .bind_address
+        .clone()
+        .unwrap_or_else(|| redirect::default_bind_address(authority));
+
+    let mut scopes: Vec<&str> = DEFAULT_SCOPES.to_vec();
+    for scope in config.extra_scopes.iter().flatten() {
+        if !scopes.contains(&scope.as_str()) {
+            scopes.push(scope);
+        }
+    }
+
+    let client_id = config.client_id.clone().unwrap();
+    // With PKCE the verifier proves who we are, so the secret is never sent or stored
+    if use_pkce {
+        config.client_secret = None;
+    }
+    let client_secret = config.client_secret.clone();
+    let pkce = use_pkce.then(Pkce::new);
+    let state = redirect::new_state();
+    // The environment can point us at a mock server, without that ending up in the config
+    let resolved = util::read_resolved_config(&path).unwrap_or_else(|e| e.exit());
+    let accounts_url = accounts_url(&resolved.config);
+
+    // Build a URL, I just ripped this from cool-spotify-blend
+    let mut auth_url = format!("{}/authorize?client_id={}&scope={}&redirect_uri={}&response_type={}&state={}",
+        accounts_url,
+        http::percent_encode(&client_id),
+        http::percent_encode(&scopes.join(" ")),
+        http::percent_encode(&redirect_uri),
+        "code",
+        state);
+    if let Some(pkce) = &pkce {
+        auth_url += &format!("&code_challenge_method=S256&code_challenge={}", pkce.challenge);
+    }
+
+    // User has to click this link and authorize
+    println!("Open the following link and authorize:\n{}", auth_url);
+
+    let code = if args.manual {
+        println!("Then paste the URL you were redirected to (or just the code) here:");
+        redirect::read_code(std::io::stdin().lock(), &state)
+    } else {
+        let listener = match TcpListener::bind(&bind_address) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Failed to bind to {}: {}\nPass --manual to paste the redirect in instead.", bind_address, e);
+                std::process::exit(spotify::error::EXIT_FAILURE);
+            }
+        };
+        redirect::wait_for_code(&listener, callback_path, &state)
+    };
+    let code = match code {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Authorization failed: {}", e);
+            std::process::exit(spotify::error::EXIT_FAILURE);
+        }
+    };
+
+    // Get the token and more importantly refresh token from spotify
+    let mut params = vec![
+        ("grant_type", "authorization_code"),
+        ("code", &code),
+        ("redirect_uri", &redirect_uri),
+        ("client_id", &client_id),
+    ];
+    if let Some(client_secret) = &client_secret {
+        params.push(("client_secret", client_secret));
+    }
+    if let Some(pkce) = &pkce {
+        params.push(("code_verifier", &pkce.verifier));
+    }
+    let res: AuthorizationRequestResponse = request_token(accounts_url, &params).unwrap_or_else(|e| e.exit("Failed to get token"));
+
+    // Whatever was cached belonged to the old authorization
+    util::clear_token_cache(&path);
+    let cache = util::TokenCache::new(res.access_token, res.expires_in, None);
+    if let Err(e) = util::write_token_cache(&path, &cache) {
+        eprintln!("Failed to write token cache: {}", e);
+    }
+
+    config.refresh_token = Some(res.refresh_token);
+    config.granted_scopes = res
+        .scope
+        .map(|s| s.split_whitespace().map(str::to_owned).collect());
+    util::write_config(&path, config).unwrap_or_else(|e| e.exit());
+
+    println!("Authorization completed.");
+}
+
+/// Whether the auth listener would know what to do with this redirect URI
+pub fn is_valid_redirect_uri(uri: &str) -> bool {
+    redirect::split_uri(uri).is_some()
+}
+
+/// Prints who we're logged in as and what the token can do
+pub fn status(path: util::ConfigPath) {
+    let file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    println!("Config: {}", util::config_file_path(&path).display());
+    println!("Profile: {}", file.profile_name(&path));
+
+    let resolved = util::read_resolved_config(&path).unwrap_or_else(|e| e.exit());
+    let config = resolved.config.clone();
+    if config.refresh_token.is_none() && resolved.access_token.is_none() {
+        println!("Not logged in.");
+        return;
+    }
+    let cached = resolved.access_token.is_none() && resolved.refresh_token == util::Source::File;
+
+    let token = match util::resolve_token(&path, resolved) {
+        Ok(token) => token,
+        Err(e) => {
+            println!("Token doesn't work: {}", e);
+            return;
+        }
+    };
+    let client = crate::spotify::SpotifyClient::new(token, &config);
+    match client.get_user_profile() {
+        Ok(user) => println!(
+            "User: {} ({})",
+            user.display_name.as_deref().unwrap_or("<no display name>"),
+            user.id.0
+        ),
+        Err(e) => e.exit("Failed to get user profile"),
+    }
+
+    match &config.granted_scopes {
+        Some(scopes) => println!("Scopes: {}", scopes.join(" ")),
+        None => println!("Scopes: unknown (authorized before scopes were recorded)"),
+    }
+    match util::read_token_cache(&path).expires_at {
+        _ if !cached => println!("Credentials come from the environment, token cache not used"),
+        Some(expires_at) if expires_at > util::now() => {
+            println!("Access token expires in {}m", (expires_at - util::now()) / 60)
+        }
+        _ => println!("No cached access token"),
+    }
+}
+
+/// Forgets the selected profile's tokens, keeping the rest of its config
+pub fn logout(path: util::ConfigPath) {
+    let mut config = util::read_config(&path).unwrap_or_else(|e| e.exit());
+    util::clear_token_cache(&path);
+    if config.refresh_token.is_none() {
+        println!("Not logged in.");
+        return;
+    }
+
+    config.refresh_token = None;
+    config.granted_scopes = None;
+    util::write_config(&path, config).unwrap_or_else(|e| e.exit());
+
+    println!("Logged out.");
+}
+
+/// Prints every profile, checking that its token still works
+pub fn list(path: util::ConfigPath) {
+    let file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    let selected = file.profile_name(&path);
+    let profiles = file.profiles();
+    if profiles.is_empty() {
+        println!("No profiles configured.");
+        return;
+    }
+
+    let width = profiles.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, config) in profiles {
+        let profile_path = util::ConfigPath {
+            profile: Some(name.to_owned()),
+            ..path.clone()
+        };
+        let status = if config.refresh_token.is_none() {
+            "not authorized".to_owned()
+        } else {
+            match util::fetch_token(&profile_path, config.clone()) {
+                Ok(_) => {
+                    let expires_at = util::read_token_cache(&profile_path).expires_at.unwrap_or(0);
+                    format!("valid, expires in {}m", expires_at.saturating_sub(util::now()) / 60)
+                }
+                Err(e) => format!("invalid: {}", e),
+            }
+        };
+        let marker = if name == selected { "*" } else { " " };
+        println!("{} {:width$}  {}", marker, name, status, width = width);
+    }
+}
+
+#[derive(Deserialize)]
+#[non_exhaustive]
+pub struct RefreshRes {
+    pub access_token: String,
+    /// Seconds until access_token stops working
+    pub expires_in: u64,
+    /// Spotify rotates refresh tokens issued through PKCE, the old one stops working
+    pub refresh_token: Option<String>,
+}
+
+impl Config {
+    pub fn check_config(&self) -> (bool, bool) {
+        let mut missing = (false, false);
+        if self.refresh_token.is_none() {
+            missing.0 = true
+        }
+        if self.client_id.is_none() {
+            missing.1 = true
+        }
+
+        missing
+    }
+
+    // No client secret is fine, that just means we authorized with PKCE
+    pub fn is_valid(&self) -> bool {
+        let pair = self.check_config();
+        !(pair.0 || pair.1)
+    }
+
+    /// Which of `needed` we know weren't granted.
+    /// Configs from before we recorded scopes are given the benefit of the doubt.
+    pub fn missing_scopes(&self, needed: &[&str]) -> Vec<String> {
+        match &self.granted_scopes {
+            None => Vec::new(),
+            Some(granted) => needed
+                .iter()
+                .filter(|s| !granted.iter().any(|g| g == *s))
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+fn accounts_url(config: &Config) -> &str {
+    config.accounts_url.as_deref().unwrap_or(ACCOUNTS_URL).trim_end_matches('/')
+}
+
+/// Posts to the token endpoint. Everything goes in the form body,
+/// keeping secrets out of URLs (and so out of error messages)
+fn request_token<T: DeserializeOwned>(accounts_url: &str, params: &[(&str, &str)]) -> Result<T, spotify::Error> {
+    let res = ureq::post(&format!("{}/api/token", accounts_url))
+        .send_form(params)
+        .map_err(spotify::Error::from_token_endpoint)?;
+    Ok(res.into_json()?)
+}
+
+pub fn refresh(config: &util::Config) -> Result<RefreshRes, spotify::Error> {
+    if !config.is_valid() {
+        let missing = match config.check_config() {
+            (true, true) => "no refresh token or client id",
+            (true, false) => "no refresh token",
+            _ => "no client id",
+        };
+        return Err(spotify::Error::Credentials(format!("{}, run auth first", missing)));
+    }
+    let refresh_token = config.refresh_token.clone().unwrap();
+    let client_id = config.client_id.clone().unwrap();
+
+    let mut params = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token.as_str()),
+        ("client_id", client_id.as_str()),
+    ];
+    if let Some(client_secret) = &config.client_secret {
+        params.push(("client_secret", client_secret));
+    }
+
+    request_token(accounts_url(config), &params)
+}
+
+/// App-only token, good for catalog and public playlist reads but nothing user specific
+pub fn client_credentials(config: &util::Config) -> Result<RefreshRes, spotify::Error> {
+    let (client_id, client_secret) = match (&config.client_id, &config.client_secret) {
+        (Some(id), Some(secret)) => (id, secret),
+        _ => {
+            return Err(spotify::Error::Credentials(
+                "an app token needs both a client id and a client secret".to_owned(),
+            ))
+        }
+    };
+
+    request_token(accounts_url(config), &[
+        ("grant_type", "client_credentials"),
+        ("client_id", client_id.as_str()),
+        ("client_secret", client_secret.as_str()),
+    ])
+}
@@ -0,0 +1,49 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+/// A code verifier and its S256 challenge, as described in RFC 7636
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    pub fn new() -> Self {
+        // 64 random bytes encode to 86 characters, comfortably inside the 43..=128 allowed
+        let mut bytes = [0u8; 64];
+        getrandom::getrandom(&mut bytes).expect("Failed to gather randomness for PKCE verifier");
+
+        Self::from_verifier(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    fn from_verifier(verifier: String) -> Self {
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkce_challenge() {
+        let pkce = Pkce::from_verifier("dBjftJeZ4CVP-mJ0kFRuM8ZcPlS4mUtR1IiL8QWwBhM".to_owned());
+        assert_eq!(pkce.challenge, "oL1pzn_c-YxDnYq6_YkiDajNf8xOVJlAjcjggmcCIqc");
+    }
+
+    #[test]
+    fn test_pkce_verifier_format() {
+        let pkce = Pkce::new();
+        assert_eq!(pkce.verifier.len(), 86);
+        assert!(pkce
+            .verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_ne!(pkce.verifier, Pkce::new().verifier);
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::io::{prelude::*, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use thiserror::Error;
+
+use crate::http::{self, Request};
+use crate::util;
+
+#[derive(Error, Debug)]
+pub enum CallbackError {
+    #[error("Failed to talk to the browser: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Spotify refused authorization: {0}")]
+    Denied(String),
+    #[error("Callback state did not match, refusing to continue")]
+    StateMismatch,
+    #[error("No authorization code found in the input")]
+    NoCode,
+}
+
+/// Random value sent with the authorize link and checked on the way back,
+/// so a request we didn't start can't hand us a code
+pub fn new_state() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("Failed to gather randomness for state");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    http::parse_pairs(query).into_iter().collect()
+}
+
+/// Pulls the code out of a callback's query, making sure it's the one we asked for
+fn check_callback(query: &HashMap<String, String>, state: &str) -> Result<String, CallbackError> {
+    if query.get("state").map(String::as_str) != Some(state) {
+        return Err(CallbackError::StateMismatch);
+    }
+    if let Some(error) = query.get("error") {
+        return Err(CallbackError::Denied(error.to_owned()));
+    }
+
+    query.get("code").cloned().ok_or(CallbackError::NoCode)
+}
+
+/// Splits "http://localhost:8888/callback" into ("localhost:8888", "/callback")
+pub fn split_uri(uri: &str) -> Option<(&str, &str)> {
+    let rest = uri.strip_prefix("http://")?;
+    match rest.find('/') {
+        Some(i) => Some((&rest[..i], &rest[i..])),
+        None => Some((rest, "/")),
+    }
+    .filter(|(authority, _)| !authority.is_empty())
+}
+
+/// Binds where the redirect URI points, keeping "localhost" off the network
+pub fn default_bind_address(authority: &str) -> String {
+    let (host, port) = authority.rsplit_once(':').unwrap_or((authority, "80"));
+    let host = match host {
+        "localhost" => "127.0.0.1",
+        host => host,
+    };
+    format!("{}:{}", host, port)
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn respond(stream: &mut TcpStream, status: &str, title: &str, message: &str) -> std::io::Result<()> {
+    let body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>spotify-cli</title></head>\
+         <body style=\"font-family: sans-serif; text-align: center; margin-top: 4em\">\
+         <h1>{}</h1><p>{}</p></body></html>",
+        html_escape(title),
+        html_escape(message)
+    );
+    stream.write_all(http::response(status, &[("Content-Type", "text/html; charset=utf-8")], &body).as_bytes())?;
+    stream.flush()
+}
+
+/// How long a connection gets to send its request. Browsers open spare connections
+/// (preconnects) that never send anything, and those shouldn't hold up the real callback.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Accepts connections until the real callback on `path` shows up,
+/// anything else (favicon.ico and friends) gets a 404 and we keep waiting
+pub fn wait_for_code(listener: &TcpListener, path: &str, state: &str) -> Result<String, CallbackError> {
+    wait_for_code_with_timeout(listener, path, state, READ_TIMEOUT)
+}
+
+fn wait_for_code_with_timeout(listener: &TcpListener, path: &str, state: &str, timeout: Duration) -> Result<String, CallbackError> {
+    for stream in listener.incoming() {
+        // One broken connection (a dropped favicon request, a silent preconnect) is no reason to give up
+        let res = stream.and_then(|mut stream| {
+            stream.set_read_timeout(Some(timeout))?;
+            handle_connection(&mut stream, path, state)
+        });
+        match res {
+            Ok(Some(res)) => return res,
+            Ok(None) => {}
+            Err(e) => {
+                if util::verbose() {
+                    eprintln!("Ignoring a connection to the redirect listener: {}", e);
+                }
+            }
+        }
+    }
+
+    unreachable!("TcpListener::incoming never returns None")
+}
+
+/// Answers one connection, returning the outcome if it was the callback
+fn handle_connection(stream: &mut TcpStream, path: &str, state: &str) -> std::io::Result<Option<Result<String, CallbackError>>> {
+    // Reading all of it means the browser doesn't see a reset before our response
+    let request = match Request::read(&mut BufReader::new(&mut *stream)) {
+        Ok(request) if request.method == "GET" => request,
+        Err(e) if e.kind() != std::io::ErrorKind::InvalidData => return Err(e),
+        _ => {
+            respond(stream, "400 Bad Request", "Bad request", "That didn't look like an HTTP request.")?;
+            return Ok(None);
+        }
+    };
+
+    let query: HashMap<String, String> = request.query.into_iter().collect();
+    let is_callback = request.path == path && (query.contains_key("code") || query.contains_key("error"));
+    if !is_callback {
+        respond(stream, "404 Not Found", "Not found", "Waiting for Spotify to redirect here.")?;
+        return Ok(None);
+    }
+
+    let res = check_callback(&query, state);
+    // The code is ours whether or not the browser hears back
+    let written = match &res {
+        Ok(_) => respond(stream, "200 OK", "Authorization complete", "You can close this tab and return to your terminal."),
+        Err(CallbackError::Denied(error)) => respond(stream, "200 OK", "Authorization failed", &format!("Spotify said: {}", error)),
+        Err(_) => respond(stream, "400 Bad Request", "Authorization failed", "The state parameter didn't match. Run auth again."),
+    };
+    if let Err(e) = written {
+        if util::verbose() {
+            eprintln!("Couldn't tell the browser how authorization went: {}", e);
+        }
+    }
+    Ok(Some(res))
+}
+
+/// For when there's no browser that can reach us: takes the URL the browser ended up at,
+/// or just the code, from the first non-empty line of `input`
+pub fn read_code(input: impl BufRead, state: &str) -> Result<String, CallbackError> {
+    let mut line = String::new();
+    for read in input.lines() {
+        line = read?;
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+    let line = line.trim();
+
+    match line.split_once('?') {
+        Some((_, query)) => {
+            // Fragments aren't sent by browsers, but they do get copied along with the URL
+            let query = query.split('#').next().unwrap_or_default();
+            check_callback(&parse_query(query), state)
+        }
+        None if line.is_empty() => Err(CallbackError::NoCode),
+        // A bare code has no state to check, but the user typed it in themselves
+        None => Ok(http::percent_decode(line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_code() {
+        let input = "http://localhost:8888/?code=abc%2Ddef&state=xyz\n".as_bytes();
+        assert_eq!(read_code(input, "xyz").unwrap(), "abc-def");
+
+        let input = "\n  abc-def  \n".as_bytes();
+        assert_eq!(read_code(input, "xyz").unwrap(), "abc-def");
+
+        let input = "http://localhost:8888/?code=abc&state=nope".as_bytes();
+        assert!(matches!(read_code(input, "xyz"), Err(CallbackError::StateMismatch)));
+
+        let input = "http://localhost:8888/?error=access_denied&state=xyz".as_bytes();
+        assert!(matches!(read_code(input, "xyz"), Err(CallbackError::Denied(e)) if e == "access_denied"));
+
+        assert!(matches!(read_code("".as_bytes(), "xyz"), Err(CallbackError::NoCode)));
+    }
+
+    #[test]
+    fn test_wait_for_code() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            // A preconnect that never says anything, and a request that hangs up right away
+            let _silent = TcpStream::connect(addr).unwrap();
+            let mut dropped = TcpStream::connect(addr).unwrap();
+            write!(dropped, "GET /favicon.ico HTTP/1.1\r\n\r\n").unwrap();
+            drop(dropped);
+
+            let mut callback = TcpStream::connect(addr).unwrap();
+            write!(callback, "GET /callback?code=abc&state=xyz HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+            let mut response = String::new();
+            callback.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let code = wait_for_code_with_timeout(&listener, "/callback", "xyz", Duration::from_millis(200));
+        assert_eq!(code.unwrap(), "abc");
+        assert!(client.join().unwrap().starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    fn test_redirect_uri() {
+        assert_eq!(split_uri("http://localhost:8888"), Some(("localhost:8888", "/")));
+        assert_eq!(split_uri("http://127.0.0.1:9000/callback"), Some(("127.0.0.1:9000", "/callback")));
+        assert_eq!(split_uri("https://example.com/"), None);
+        assert_eq!(split_uri("http:///callback"), None);
+
+        assert_eq!(default_bind_address("localhost:8888"), "127.0.0.1:8888");
+        assert_eq!(default_bind_address("0.0.0.0:9000"), "0.0.0.0:9000");
+        assert_eq!(default_bind_address("localhost"), "127.0.0.1:80");
+    }
+}
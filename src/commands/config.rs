@@ -0,0 +1,457 @@
+use std::io::{self, BufRead, Write};
+
+use crate::commands::auth;
+use crate::util::{self, ConfigError, Source};
+
+/// Keys that belong to a profile, at the top level or under [profiles.<name>]
+const PROFILE_KEYS: &[&str] = &[
+    "client_id",
+    "client_secret",
+    "refresh_token",
+    "redirect_uri",
+    "bind_address",
+    "extra_scopes",
+    "granted_scopes",
+    "api_url",
+    "accounts_url",
+    "max_retries",
+];
+/// Tables that belong to a profile, checked separately
+const PROFILE_TABLES: &[&str] = &["aliases"];
+/// Keys that only make sense at the top level
+const FILE_KEYS: &[&str] = &["default_profile", "encryption_salt", "profiles"];
+
+/// Asks for the client id and secret, then writes them to the selected profile
+pub fn init(path: util::ConfigPath) {
+    let mut file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    let profile = file.profile_name(&path).to_owned();
+    let file_path = util::config_file_path(&path);
+    if file.get(&profile).is_some_and(|c| c.client_id.is_some()) {
+        println!(
+            "Profile {} in {} is already set up, change it with `config set`.",
+            profile,
+            file_path.display()
+        );
+        return;
+    }
+
+    println!("Setting up profile {} in {}", profile, file_path.display());
+    println!("Find these on your app's page at https://developer.spotify.com/dashboard");
+    let mut config = file.get(&profile).cloned().unwrap_or_default();
+    let client_id = prompt("Client ID: ");
+    if client_id.is_empty() {
+        ConfigError::invalid(&path, "A client id is required").exit();
+    }
+    config.client_id = Some(client_id);
+    let client_secret = prompt("Client secret (leave empty to use PKCE instead): ");
+    config.client_secret = (!client_secret.is_empty()).then_some(client_secret);
+
+    let pkce = config.client_secret.is_none();
+    file.set(&profile, config);
+    util::write_config_file(&path, &file).unwrap_or_else(|e| e.exit());
+
+    println!(
+        "Config written. Now run `spotify-cli auth{}`.",
+        if pkce { " --pkce" } else { "" }
+    );
+}
+
+fn prompt(message: &str) -> String {
+    print!("{}", message);
+    io::stdout().flush().ok();
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).ok();
+    line.trim().to_owned()
+}
+
+/// Prints one value from the selected profile, exiting with 1 if it isn't set
+pub fn get(path: util::ConfigPath, key: &str) {
+    let file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    let config = file.get(file.profile_name(&path)).cloned().unwrap_or_default();
+
+    let value = match key {
+        "default_profile" => file.default_profile.clone(),
+        "client_id" => config.client_id,
+        "client_secret" => config.client_secret,
+        "refresh_token" => config.refresh_token,
+        "redirect_uri" => config.redirect_uri,
+        "bind_address" => config.bind_address,
+        "extra_scopes" => config.extra_scopes.map(|s| s.join(" ")),
+        "granted_scopes" => config.granted_scopes.map(|s| s.join(" ")),
+        "api_url" => config.api_url,
+        "accounts_url" => config.accounts_url,
+        "max_retries" => config.max_retries.map(|n| n.to_string()),
+        key => unknown_key(&path, key),
+    };
+    match value {
+        Some(value) => println!("{}", value),
+        None => std::process::exit(1),
+    }
+}
+
+/// Sets (or with `value` None, removes) one value in the selected profile
+pub fn set(path: util::ConfigPath, key: &str, value: Option<&str>) {
+    let mut file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    let profile = file.profile_name(&path).to_owned();
+    let mut config = file.get(&profile).cloned().unwrap_or_default();
+
+    let string = value.map(str::to_owned);
+    // Lists can be given as "a b" or "a,b"
+    let list = value.map(|v| {
+        v.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<String>>()
+    });
+    match key {
+        "default_profile" => file.default_profile = string,
+        "client_id" => config.client_id = string,
+        "client_secret" => config.client_secret = string,
+        "refresh_token" => config.refresh_token = string,
+        "redirect_uri" => {
+            if let Some(uri) = &string {
+                if !auth::is_valid_redirect_uri(uri) {
+                    ConfigError::invalid(&path, format!("redirect_uri {} should look like http://host:port/path", uri)).exit();
+                }
+            }
+            config.redirect_uri = string
+        }
+        "bind_address" => config.bind_address = string,
+        "extra_scopes" => config.extra_scopes = list,
+        "granted_scopes" => config.granted_scopes = list,
+        "api_url" => config.api_url = string,
+        "accounts_url" => config.accounts_url = string,
+        "max_retries" => {
+            config.max_retries = match value.map(str::parse).transpose() {
+                Ok(max_retries) => max_retries,
+                Err(_) => ConfigError::invalid(&path, "max_retries should be a whole number").exit(),
+            }
+        }
+        key => unknown_key(&path, key),
+    }
+
+    file.set(&profile, config);
+    util::write_config_file(&path, &file).unwrap_or_else(|e| e.exit());
+}
+
+fn unknown_key(path: &util::ConfigPath, key: &str) -> ! {
+    ConfigError::invalid(
+        path,
+        format!("Unknown key {}, expected one of: default_profile, {}", key, PROFILE_KEYS.join(", ")),
+    )
+    .exit()
+}
+
+/// Checks the whole file, reporting every problem rather than just the first
+pub fn validate(path: util::ConfigPath) {
+    let file_path = util::config_file_path(&path);
+    let text = match util::read_config_text(&path).unwrap_or_else(|e| e.exit()) {
+        Some(text) => text,
+        None => ConfigError::invalid(&path, "No config file, create one with `config init`").exit(),
+    };
+    let file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+
+    let problems = find_problems(&text, &file);
+    if problems.is_empty() {
+        println!("{} is valid.", file_path.display());
+        return;
+    }
+    for (line, problem) in problems {
+        eprintln!(
+            "{}",
+            ConfigError::Invalid {
+                path: file_path.clone(),
+                line,
+                problem,
+            }
+        );
+    }
+    std::process::exit(ConfigError::EXIT_CODE);
+}
+
+fn find_problems(text: &str, file: &util::ConfigFile) -> Vec<(Option<usize>, String)> {
+    let mut problems: Vec<(Option<usize>, String)> = Vec::new();
+
+    // Serde happily ignores keys it doesn't know, so typos have to be caught here
+    if let Ok(table) = text.parse::<toml::Table>() {
+        for key in table.keys() {
+            if ![PROFILE_KEYS, PROFILE_TABLES, FILE_KEYS].concat().contains(&key.as_str()) {
+                problems.push((find_line(text, None, key), format!("unknown key {}", key)));
+            }
+        }
+        if let Some(toml::Value::Table(profiles)) = table.get("profiles") {
+            for (name, profile) in profiles {
+                let section = format!("profiles.{}", name);
+                for key in profile.as_table().map(|t| t.keys().collect()).unwrap_or(Vec::new()) {
+                    if ![PROFILE_KEYS, PROFILE_TABLES].concat().contains(&key.as_str()) {
+                        problems.push((
+                            find_line(text, Some(&section), key),
+                            format!("unknown key {} in profile {}", key, name),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(default) = &file.default_profile {
+        if file.get(default).is_none() {
+            problems.push((
+                find_line(text, None, "default_profile"),
+                format!("default_profile is {}, but there's no such profile", default),
+            ));
+        }
+    }
+
+    let profiles = file.profiles();
+    if profiles.is_empty() {
+        problems.push((None, "no profiles have a client_id".to_owned()));
+    }
+    for (name, config) in profiles {
+        let section = (name != util::DEFAULT_PROFILE || file.profiles.contains_key(name))
+            .then(|| format!("profiles.{}", name));
+        if config.client_id.is_none() {
+            let line = section.as_deref().and_then(|s| find_section_line(text, s));
+            problems.push((line, format!("profile {} has no client_id", name)));
+        }
+        let aliases_section = match &section {
+            Some(section) => format!("{}.aliases", section),
+            None => "aliases".to_owned(),
+        };
+        for (alias, id) in &config.aliases {
+            if crate::spotify::playlist::parse_playlist_id(id) != Some(id.as_str()) {
+                problems.push((
+                    find_line(text, Some(&aliases_section), alias),
+                    format!("alias {} should be a playlist ID, not {}", alias, id),
+                ));
+            }
+        }
+        if let Some(uri) = &config.redirect_uri {
+            if !auth::is_valid_redirect_uri(uri) {
+                problems.push((
+                    find_line(text, section.as_deref(), "redirect_uri"),
+                    format!("redirect_uri {} should look like http://host:port/path", uri),
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// 1-based line of `key = ...` within `section` (None for the top level)
+fn find_line(text: &str, section: Option<&str>, key: &str) -> Option<usize> {
+    let mut current: Option<String> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            current = Some(line.trim_matches(|c| c == '[' || c == ']').replace('"', "").trim().to_owned());
+        } else if current.as_deref() == section {
+            if let Some((k, _)) = line.split_once('=') {
+                if k.trim().trim_matches('"') == key {
+                    return Some(i + 1);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn find_section_line(text: &str, section: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| line.trim().trim_matches(|c| c == '[' || c == ']').replace('"', "").trim() == section)
+        .map(|i| i + 1)
+}
+
+pub fn path(path: util::ConfigPath) {
+    println!("{}", util::config_file_path(&path).display());
+}
+
+/// Prints the selected profile's config, secrets shortened.
+/// With `resolved`, environment overrides are applied and each value says where it came from.
+pub fn show(path: util::ConfigPath, resolved: bool) {
+    let file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    let profile = file.profile_name(&path);
+    let file_source = format!("{} [{}]", util::config_file_path(&path).display(), profile);
+
+    if !resolved {
+        let config = file.get(profile).cloned().unwrap_or_default();
+        println!("# {}", file_source);
+        print_value("client_id", config.client_id.as_deref(), false, None);
+        print_value("client_secret", config.client_secret.as_deref(), true, None);
+        print_value("refresh_token", config.refresh_token.as_deref(), true, None);
+        if config.api_url.is_some() {
+            print_value("api_url", config.api_url.as_deref(), false, None);
+        }
+        if config.accounts_url.is_some() {
+            print_value("accounts_url", config.accounts_url.as_deref(), false, None);
+        }
+        return;
+    }
+
+    let resolved = util::read_resolved_config(&path).unwrap_or_else(|e| e.exit());
+    let source = |source: Source, var: &str| -> String {
+        match source {
+            Source::Env => format!("env {}", var),
+            Source::File => file_source.clone(),
+            Source::Unset => "unset".to_owned(),
+        }
+    };
+    print_value(
+        "client_id",
+        resolved.config.client_id.as_deref(),
+        false,
+        Some(source(resolved.client_id, util::ENV_CLIENT_ID)),
+    );
+    print_value(
+        "client_secret",
+        resolved.config.client_secret.as_deref(),
+        true,
+        Some(source(resolved.client_secret, util::ENV_CLIENT_SECRET)),
+    );
+    print_value(
+        "refresh_token",
+        resolved.config.refresh_token.as_deref(),
+        true,
+        Some(source(resolved.refresh_token, util::ENV_REFRESH_TOKEN)),
+    );
+    print_value(
+        "access_token",
+        resolved.access_token.as_deref(),
+        true,
+        Some(source(resolved.access_token_source, util::ENV_ACCESS_TOKEN)),
+    );
+    if resolved.config.api_url.is_some() {
+        print_value(
+            "api_url",
+            resolved.config.api_url.as_deref(),
+            false,
+            Some(source(resolved.api_url, util::ENV_API_URL)),
+        );
+    }
+    if resolved.config.accounts_url.is_some() {
+        print_value(
+            "accounts_url",
+            resolved.config.accounts_url.as_deref(),
+            false,
+            Some(source(resolved.accounts_url, util::ENV_ACCOUNTS_URL)),
+        );
+    }
+}
+
+fn print_value(key: &str, value: Option<&str>, secret: bool, source: Option<String>) {
+    let value = match value {
+        None => "<unset>".to_owned(),
+        Some(v) if secret => redact(v),
+        Some(v) => format!("\"{}\"", v),
+    };
+    match source {
+        Some(source) => println!("{:13} = {:14} # {}", key, value, source),
+        None => println!("{:13} = {}", key, value),
+    }
+}
+
+/// Enough of a secret to tell two apart, not enough to use
+fn redact(secret: &str) -> String {
+    let prefix: String = secret.chars().take(4).collect();
+    format!("\"{}…\"", prefix)
+}
+
+/// Encrypts the secrets in the config (and token caches) with a passphrase
+#[cfg(feature = "encrypted_config")]
+pub fn encrypt(path: util::ConfigPath) {
+    let mut file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    if file.encryption_salt.is_some() {
+        println!("Config is already encrypted.");
+        return;
+    }
+
+    let salt = crate::crypt::new_salt();
+    if let Err(e) = crate::crypt::key_for(&salt, true) {
+        eprintln!("Failed to encrypt config: {}", e);
+        std::process::exit(1);
+    }
+
+    let caches = read_caches(&path, &file);
+    file.encryption_salt = Some(salt);
+    util::write_config_file(&path, &file).unwrap_or_else(|e| e.exit());
+    write_caches(caches);
+
+    println!("Config encrypted.");
+}
+
+/// Puts the config (and token caches) back in plaintext
+#[cfg(feature = "encrypted_config")]
+pub fn decrypt(path: util::ConfigPath) {
+    let mut file = util::read_config_file(&path).unwrap_or_else(|e| e.exit());
+    if file.encryption_salt.is_none() {
+        println!("Config isn't encrypted.");
+        return;
+    }
+
+    let caches = read_caches(&path, &file);
+    file.encryption_salt = None;
+    util::write_config_file(&path, &file).unwrap_or_else(|e| e.exit());
+    write_caches(caches);
+
+    println!("Config decrypted.");
+}
+
+// Token caches are read under the old setting and written back under the new one
+#[cfg(feature = "encrypted_config")]
+fn read_caches(path: &util::ConfigPath, file: &util::ConfigFile) -> Vec<(util::ConfigPath, util::TokenCache)> {
+    file.profiles()
+        .into_iter()
+        .map(|(name, _)| {
+            let profile_path = util::ConfigPath {
+                profile: Some(name.to_owned()),
+                ..path.clone()
+            };
+            let cache = util::read_token_cache(&profile_path);
+            (profile_path, cache)
+        })
+        .filter(|(_, cache)| cache.access_token.is_some())
+        .collect()
+}
+
+#[cfg(feature = "encrypted_config")]
+fn write_caches(caches: Vec<(util::ConfigPath, util::TokenCache)>) {
+    for (path, cache) in caches {
+        if let Err(e) = util::write_token_cache(&path, &cache) {
+            eprintln!("Failed to rewrite token cache: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_problems() {
+        let text = r#"client_id = "abc"
+clientsecret = "typo"
+default_profile = "nope"
+
+[profiles.office]
+redirect_uri = "https://example.com"
+colour = "blue"
+"#;
+        let file: util::ConfigFile = toml::from_str(text).unwrap();
+        let problems = find_problems(text, &file);
+
+        assert!(problems.contains(&(Some(2), "unknown key clientsecret".to_owned())));
+        assert!(problems.contains(&(Some(3), "default_profile is nope, but there's no such profile".to_owned())));
+        assert!(problems.contains(&(Some(5), "profile office has no client_id".to_owned())));
+        assert!(problems.contains(&(
+            Some(6),
+            "redirect_uri https://example.com should look like http://host:port/path".to_owned()
+        )));
+        assert!(problems.contains(&(Some(7), "unknown key colour in profile office".to_owned())));
+        assert_eq!(problems.len(), 5);
+
+        let text = "client_id = \"abc\"\n";
+        let file: util::ConfigFile = toml::from_str(text).unwrap();
+        assert!(find_problems(text, &file).is_empty());
+    }
+}
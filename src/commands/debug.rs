@@ -1,49 +1,61 @@
-use std::path::PathBuf;
-
 #[cfg(feature = "json_export")]
 use serde_json;
 
-use crate::{commands::auth, spotify, util};
+use crate::{commands::{alias, auth}, spotify::types::PlaylistItem, util};
+
+/// Dumps every item of the playlist, or with `added_by` only those that user added
+pub fn dump_playlist(path: util::ConfigPath, playlist: &str, added_by: Option<&str>) {
+    let client = util::get_read_client(&path);
+    let playlist = &alias::resolve(&path, playlist);
 
-pub fn dump_playlist(path: PathBuf, playlist: &str) {
-    let token = util::get_token(&path);
+    let mut data = client
+        .get_playlist_items(playlist)
+        .unwrap_or_else(|e| e.exit("Failed to get playlist items"));
+    if let Some(user) = added_by {
+        data.retain(|item| item.added_by.as_ref().is_some_and(|by| by.id.0 == user));
+    }
 
-    let res = spotify::playlist::get_playlist_items(playlist, &token);
+    // Easy to miss in a long dump, and they won't survive being copied elsewhere
+    let count = |kind: fn(&PlaylistItem) -> bool| data.iter().filter(|item| kind(&item.track)).count();
+    let episodes = count(|item| matches!(item, PlaylistItem::Episode(_)));
+    let local = count(|item| matches!(item, PlaylistItem::Local(_)));
+    let unavailable = count(|item| matches!(item, PlaylistItem::Unavailable { .. }));
+    if episodes + local + unavailable > 0 {
+        eprintln!(
+            "Besides tracks there are {} episodes, {} local files and {} unavailable items",
+            episodes, local, unavailable
+        );
+    }
 
     // We will use serde_json to export as JSON if the "json_export" feature is enabled,
     // otherwise, we'll just dump the derived Debug of everything.
     if cfg!(feature = "json_export") {
         #[cfg(feature = "json_export")]
         {
-        match res {
-            Ok(data) => {
-                let table = serde_json::to_string(&data);
-                match table {
-                    Ok(table) => {
-                        println!("{}", table.to_string());
-                    }
-                    Err(e) => {
-                        println!("Failed to serialize data:\n{:?}", e);
-                    }
-                }
+        let table = serde_json::to_string(&data);
+        match table {
+            Ok(table) => {
+                println!("{}", table);
             }
             Err(e) => {
-                println!("Failed to get playlist items:\n{:?}", e);
+                eprintln!("Failed to serialize data: {}", e);
+                std::process::exit(1);
             }
         }
         }
     } else {
-        println!("{:?}", res);
+        println!("{:?}", data);
     }
 }
 
-pub fn clear_playlist(path: PathBuf, playlist: &str) {
-    let token = util::get_token(&path);
+pub fn clear_playlist(path: util::ConfigPath, playlist: &str) {
+    let client = util::get_client(&path, auth::SCOPES_PLAYLIST_MODIFY);
+    let playlist = &alias::resolve(&path, playlist);
 
-    let res = spotify::playlist::set_playlist(playlist, &[], &token);
+    let res = client.set_playlist(playlist, &[]);
 
     match res {
         Ok(_) => println!("Playlist cleared."),
-        Err(e) => println!("Failed to clear playlist:\n{:?}", e)
+        Err(e) => e.exit("Failed to clear playlist"),
     }
 }
@@ -1,4 +1,6 @@
 pub mod playlist;
 pub mod user;
 pub mod auth;
+pub mod config;
 pub mod debug;
+pub mod alias;
@@ -1,6 +1,6 @@
 use clap::ValueEnum;
 
-use crate::{util, commands::auth, spotify::{self, types::Token}};
+use crate::{util, commands::{alias, auth}, spotify::{types::Uri, Error, SpotifyClient}};
 
 #[derive(ValueEnum, Clone, Copy)]
 pub enum CopyPreposition {
@@ -9,71 +9,95 @@ pub enum CopyPreposition {
     IntoNew,
 }
 
-pub fn run(path: std::path::PathBuf, a: &str, prep: &CopyPreposition, b: &str) {
-    let token = util::get_token(&path);
+pub fn run(path: util::ConfigPath, a: &str, prep: &CopyPreposition, b: &str) {
+    let client = util::get_client(&path, auth::SCOPES_PLAYLIST_MODIFY);
+    let a = &alias::resolve(&path, a);
+    // Into a new playlist, b is the new playlist's name
+    let b = &match prep {
+        CopyPreposition::IntoNew => b.to_owned(),
+        _ => alias::resolve(&path, b),
+    };
 
     match prep {
         CopyPreposition::From => {
             println!("Copying into {} from {}", a, b);
-            run_order_const(&token, b, a)
+            run_order_const(&client, b, a)
         }
         CopyPreposition::Into => {
             println!("Copying from {} into {}", a, b);
-            run_order_const(&token, a, b)
+            run_order_const(&client, a, b)
         }
         CopyPreposition::IntoNew => {
             println!("Copying from {} into new playlist: {}", a, b);
-            run_into_new(&token, a, b)
+            run_into_new(&client, a, b)
         }
     }
 }
 
-fn run_order_const(token: &Token, from: &str, to: &str) {
-    let new_uris = spotify::playlist::get_playlist_uris(from, token);
+fn run_order_const(client: &SpotifyClient, from: &str, to: &str) {
+    let new_uris = read_uris(client, from);
     match new_uris {
         Ok(uris) => {
-            let res = spotify::playlist::add_to_playlist(to, &uris, token);
+            let res = client.add_to_playlist(to, &uris);
             match res {
                 Ok(_) => {
                     println!("Copy complete.")
                 },
                 Err(e) => {
-                    println!("Failed to add to playlist\n{:?}", e);
+                    e.exit("Failed to add to playlist");
                 }
             }
         },
         Err(e) => {
-            println!("Failed to get from playlist\n{:?}", e);
+            e.exit("Failed to get from playlist");
         }
     }
 }
 
-fn run_into_new(token: &Token, from: &str, to: &str) {
-    let new_playlist = spotify::playlist::create_playlist(to, token);
+fn run_into_new(client: &SpotifyClient, from: &str, to: &str) {
+    let new_playlist = client.create_playlist(to);
 
     match new_playlist {
         Ok((uri, link)) => {
-            let new_uris = spotify::playlist::get_playlist_uris(from, token);
+            let new_uris = read_uris(client, from);
             match new_uris {
                 Ok(uris) => {
-                    let res = spotify::playlist::set_playlist(&uri.0, &uris, token);
+                    let res = client.set_playlist(&uri.0, &uris);
                     match res {
                         Ok(_) => {
                             println!("Copy complete.\nURI:{}\nLink:{}", uri, link);
                         }
                         Err(e) => {
-                            println!("Failed to set playlist contents:\n{:?}", e);
+                            e.exit("Failed to set playlist contents");
                         }
                     }
                 }
                 Err(e) => {
-                    println!("Failed to get from playlist\n{:?}", e);
+                    e.exit("Failed to get from playlist");
                 }
             }
         }
         Err(e) => {
-            println!("Failed to make a new playlist:\n{:?}", e);
+            e.exit("Failed to make a new playlist");
         }
     }
 
 }
+
+/// The tracks and episodes of the playlist, in order. Local files and unavailable tracks
+/// can't be added through the API, so they're left out (and counted on stderr).
+fn read_uris(client: &SpotifyClient, from: &str) -> Result<Vec<Uri>, Error> {
+    let items = client.get_playlist_items(from)?;
+    let uris: Vec<Uri> = items
+        .iter()
+        .filter(|item| item.track.is_catalog())
+        .filter_map(|item| item.track.uri().cloned())
+        .collect();
+    if uris.len() < items.len() {
+        eprintln!(
+            "Skipping {} local files and unavailable tracks, which can't be copied",
+            items.len() - uris.len()
+        );
+    }
+    Ok(uris)
+}
@@ -1,3 +1,37 @@
 pub mod copy;
 pub mod sort;
 pub mod new;
+
+use clap::ValueEnum;
+
+use crate::spotify::Error;
+
+/// How many times `retry` re-reads a playlist that keeps changing before it gives up
+const MAX_CONFLICT_RETRIES: usize = 3;
+
+/// What to do when a playlist changed between reading and writing it
+#[derive(ValueEnum, Debug, Copy, Clone, Default)]
+pub enum OnConflict {
+    /// Leave the playlist alone and exit
+    #[default]
+    Abort,
+    /// Read the playlist again and redo the work on what's there now
+    Retry,
+}
+
+impl OnConflict {
+    /// Runs `attempt`, which reads a playlist and writes it back, again whenever it fails with
+    /// [`Error::PlaylistChanged`] and we're allowed to retry
+    pub fn run<T>(self, mut attempt: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+        let mut retries = 0;
+        loop {
+            match attempt() {
+                Err(Error::PlaylistChanged) if matches!(self, OnConflict::Retry) && retries < MAX_CONFLICT_RETRIES => {
+                    retries += 1;
+                    eprintln!("The playlist changed while we were working on it, reading it again");
+                }
+                res => return res,
+            }
+        }
+    }
+}
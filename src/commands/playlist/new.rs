@@ -1,16 +1,16 @@
-use crate::{util, commands::auth, spotify};
+use crate::{util, commands::auth};
 
-pub fn run(path: std::path::PathBuf, name: &str) {
-    let token = util::get_token(&path);
+pub fn run(path: util::ConfigPath, name: &str) {
+    let client = util::get_client(&path, auth::SCOPES_PLAYLIST_MODIFY);
 
-    let res = spotify::playlist::create_playlist(name, &token);
+    let res = client.create_playlist(name);
 
     match res {
         Ok((uri, href)) => {
             println!("Creation completed.\nURI: {}\nLink: {}", uri, href);
         }
         Err(e) => {
-            println!("Playlist creation failed:\n{:?}", e);
+            e.exit("Playlist creation failed");
         }
     }
 }
@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 mod sort;
 mod parsed_release_date;
 
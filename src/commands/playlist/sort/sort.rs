@@ -2,7 +2,7 @@ use std::cmp::Ordering;
 
 use clap::ValueEnum;
 
-use crate::{commands::auth, spotify::{self, types::TrackObject, types::Uri}, util};
+use crate::{commands::{alias, auth, playlist::OnConflict}, spotify::{self, types::PlaylistItem, types::PlaylistTrackObject, types::Uri, Error}, util};
 use spotify::types;
 use crate::commands::playlist::sort::parsed_release_date::ParsedReleaseDate;
 
@@ -10,26 +10,28 @@ use crate::commands::playlist::sort::parsed_release_date::ParsedReleaseDate;
 #[non_exhaustive]
 pub enum SortMethod {
     AlbumRelease,
+    /// Episodes have no track number, so they go after the tracks
     TrackNumber,
+    /// Oldest first, with items from before Spotify kept track at the very start
+    DateAdded,
 }
 
 impl SortMethod {
-    pub fn cmp(&self, a: &types::TrackObject, b: &types::TrackObject) -> Ordering {
+    pub fn cmp(&self, a: &types::PlaylistTrackObject, b: &types::PlaylistTrackObject) -> Ordering {
         match self {
-            Self::AlbumRelease => self.cmp_album_release(a, b),
-            Self::TrackNumber => self.cmp_track_number(a, b),
+            Self::DateAdded => self.cmp_date_added(a, b),
+            // Local files and unavailable items have no album or track number, so they go last
+            _ if !a.track.is_catalog() || !b.track.is_catalog() => b.track.is_catalog().cmp(&a.track.is_catalog()),
+            Self::AlbumRelease => self.cmp_album_release(release_date(&a.track), release_date(&b.track)),
+            Self::TrackNumber => self.cmp_track_number(&a.track, &b.track),
         }
     }
 
-    fn cmp_album_release(&self, a: &types::TrackObject, b: &types::TrackObject) -> Ordering {
+    fn cmp_album_release(&self, a: Option<&str>, b: Option<&str>) -> Ordering {
         let a_rd = a
-            .album.as_ref()
-            .map(|album| album.release_date.as_str())
             .ok_or(Err::<ParsedReleaseDate, String>("No album".to_owned()))
             .map(ParsedReleaseDate::try_from);
         let b_rd = b
-            .album.as_ref()
-            .map(|album| album.release_date.as_str())
             .ok_or(Err::<ParsedReleaseDate, String>("No album".to_owned()))
             .map(ParsedReleaseDate::try_from);
 
@@ -41,35 +43,72 @@ impl SortMethod {
         }
     }
 
-    fn cmp_track_number(&self, a: &types::TrackObject, b: &types::TrackObject) -> Ordering {
-        let a_tn = a.track_number;
-        let b_tn = b.track_number;
+    fn cmp_track_number(&self, a: &PlaylistItem, b: &PlaylistItem) -> Ordering {
+        // Episodes have no track number, so they go last like the other items without one
+        let track_number = |item: &PlaylistItem| match item {
+            PlaylistItem::Track(track) => Some(track.track_number),
+            _ => None,
+        };
 
-        a_tn.cmp(&b_tn)
+        match (track_number(a), track_number(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        }
     }
-}
-
-pub fn run(path: std::path::PathBuf, playlist: &str, methods: &Vec<SortMethod>) {
-    let token = util::get_token(&path);
 
-    let tracks = spotify::playlist::get_playlist_items(playlist, &token);
-    if tracks.is_err() {
-        println!("Failed to get playlist: {:?}", tracks.unwrap_err());
-        return;
+    fn cmp_date_added(&self, a: &types::PlaylistTrackObject, b: &types::PlaylistTrackObject) -> Ordering {
+        // Always UTC and zero-padded, so comparing the text is comparing the times
+        a.added_at.cmp(&b.added_at)
     }
-    let tracks = tracks.unwrap();
+}
 
-    let mut chunks: Vec<Vec<TrackObject>> = vec![tracks];
-    for method in methods {
-        chunks = run_sort_round_by(chunks, *method);
+/// The album's release date for a track, the episode's own for an episode
+fn release_date(item: &PlaylistItem) -> Option<&str> {
+    match item {
+        PlaylistItem::Track(track) => track.album.as_ref().map(|album| album.release_date.as_str()),
+        PlaylistItem::Episode(episode) => episode.release_date.as_deref(),
+        _ => None,
     }
+}
+
+pub fn run(path: util::ConfigPath, playlist: &str, methods: &Vec<SortMethod>, on_conflict: OnConflict, rewrite: bool) {
+    let client = util::get_client(&path, auth::SCOPES_PLAYLIST_MODIFY);
+    let playlist = &alias::resolve(&path, playlist);
 
-    let uris_ordered: Vec<Uri> = chunks.into_iter().flatten().map(|t| t.uri).collect();
+    let res = on_conflict.run(|| {
+        let (snapshot, items) = client.get_playlist_snapshot_items(playlist)?;
 
-    let res = spotify::playlist::set_playlist(playlist, &uris_ordered, &token);
+        // Items are sorted by where they were, which tells them apart even without a URI
+        let positions: Vec<usize> = (0..items.len()).collect();
+        let mut chunks: Vec<Vec<usize>> = vec![positions.clone()];
+        for method in methods {
+            chunks = run_sort_round_by(&items, chunks, *method);
+        }
+
+        let ordered: Vec<usize> = chunks.into_iter().flatten().collect();
+
+        if rewrite {
+            if let Some(item) = items.iter().find(|item| !item.track.is_catalog()) {
+                return Err(Error::Unwritable {
+                    uri: item.track.uri().cloned(),
+                });
+            }
+            let uris_ordered: Vec<Uri> = ordered.iter().filter_map(|&i| items[i].track.uri().cloned()).collect();
+            // A collaborator's changes since the read would be lost by the rewrite. Replacing
+            // doesn't take a snapshot ID, so this is as close to the write as the check can get,
+            // and an edit in between still gets lost.
+            client.check_snapshot(playlist, &snapshot)?;
+            return client.set_playlist(playlist, &uris_ordered);
+        }
+        let moves = client.reorder_playlist(playlist, &snapshot, &positions, &ordered)?;
+        if util::verbose() {
+            eprintln!("Sorted with {} moves", moves);
+        }
+        Ok(())
+    });
     match res {
         Err(e) => {
-            println!("Failed to set playlist:\n{:?}", e);
+            e.exit("Failed to sort playlist");
         }
         Ok(_) => {
             println!("Sorting complete.");
@@ -77,13 +116,18 @@ pub fn run(path: std::path::PathBuf, playlist: &str, methods: &Vec<SortMethod>)
     }
 }
 
-fn run_sort_round_by(chunks: Vec<Vec<TrackObject>>, method: SortMethod) -> Vec<Vec<TrackObject>> {
-    let mut out: Vec<Vec<TrackObject>> = Vec::new();
+/// Sorts each chunk of positions in `items` by `method`, splitting them further where
+/// `method` can tell items apart. Items it can't stay in playlist order.
+fn run_sort_round_by(items: &[PlaylistTrackObject], chunks: Vec<Vec<usize>>, method: SortMethod) -> Vec<Vec<usize>> {
+    let mut out: Vec<Vec<usize>> = Vec::new();
     for mut chunk in chunks {
-        chunk.sort_unstable_by(|a,b| method.cmp(a,b));
-        let mut new: Vec<TrackObject> = vec![chunk.remove(0)];
+        if chunk.is_empty() {
+            continue;
+        }
+        chunk.sort_by(|&a, &b| method.cmp(&items[a], &items[b]));
+        let mut new: Vec<usize> = vec![chunk.remove(0)];
         while !chunk.is_empty() {
-            match method.cmp(new.last().unwrap(), chunk.first().unwrap()) {
+            match method.cmp(&items[*new.last().unwrap()], &items[chunk[0]]) {
                 Ordering::Equal => {
                     new.push(chunk.remove(0))
                 },
@@ -99,3 +143,36 @@ fn run_sort_round_by(chunks: Vec<Vec<TrackObject>>, method: SortMethod) -> Vec<V
     // println!("sort.rs:110\n{:?}\n-----", out);
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(track: &str) -> PlaylistTrackObject {
+        let item = format!(r#"{{"added_at":null,"added_by":null,"is_local":false,"track":{}}}"#, track);
+        ureq::serde_json::from_str(&item).unwrap()
+    }
+
+    fn track(number: u16) -> PlaylistTrackObject {
+        item(&format!(
+            r#"{{"href":"h","id":"{0}","name":"{0}","uri":"spotify:track:{0}","track_number":{0}}}"#,
+            number
+        ))
+    }
+
+    #[test]
+    fn test_sort_keeps_everything() {
+        let items = vec![
+            item(r#"{"name":"Local","uri":"spotify:local:A:B:Local:100"}"#),
+            track(2),
+            item("null"),
+            track(1),
+            item(r#"{"type":"episode","href":"h","id":"e","name":"Episode","uri":"spotify:episode:e"}"#),
+        ];
+        let chunks = run_sort_round_by(&items, vec![(0..items.len()).collect()], SortMethod::TrackNumber);
+        // Episodes have no track number so they follow the tracks, and what can't be sorted goes last in playlist order
+        assert_eq!(chunks.concat(), vec![3, 1, 4, 0, 2]);
+
+        assert!(run_sort_round_by(&[], vec![vec![]], SortMethod::AlbumRelease).is_empty());
+    }
+}
@@ -5,22 +5,25 @@ use crate::spotify::types::Uri;
 use serde_json;
 
 pub fn run(
-    path: std::path::PathBuf,
+    path: crate::util::ConfigPath,
     term: &super::Term,
     number: u16,
     name: Option<&str>,
     dump: Option<bool>,
 ) {
-    let token = crate::util::get_token(&path);
+    let dump = dump.is_some_and(|b| b);
+    let scopes: &[&str] = if dump {
+        &["user-top-read"]
+    } else {
+        &["user-top-read", "playlist-modify-public", "playlist-modify-private"]
+    };
+    let client = crate::util::get_client(&path, scopes);
 
-    let tracks = crate::spotify::user::get_user_top_tracks(&token, &(Into::<TopTerm>::into(*term)), number);
-    if let Err(e) = tracks {
-        println!("Failed to get top tracks:\n{:?}", e);
-        return;
-    }
-    let tracks = tracks.unwrap();
+    let tracks = client
+        .get_user_top_tracks(&(Into::<TopTerm>::into(*term)), number)
+        .unwrap_or_else(|e| e.exit("Failed to get top tracks"));
 
-    if dump.is_some_and(|b| b) {
+    if dump {
         if cfg!(feature = "json_export") {
             #[cfg(feature = "json_export")]
             {
@@ -29,7 +32,8 @@ pub fn run(
                         println!("{}", data);
                     }
                     Err(e) => {
-                        println!("Failed to serialize tracks:\n{:?}", e);
+                        eprintln!("Failed to serialize tracks: {}", e);
+                        std::process::exit(1);
                     }
                 }
             }
@@ -42,21 +46,17 @@ pub fn run(
     let name = name.expect("Clap should have ensured this was required");
     let track_uris: Vec<Uri> = tracks.into_iter().map(|t| t.uri).collect();
 
-    let new_playlist_res = crate::spotify::playlist::create_playlist(name, &token);
-    if let Err(e) = new_playlist_res {
-        println!("Failed to create new playlist:\n{:?}", e);
-        return;
-    }
-    let new_playlist_res = new_playlist_res.unwrap();
+    let new_playlist_res = client
+        .create_playlist(name)
+        .unwrap_or_else(|e| e.exit("Failed to create new playlist"));
     
-    let set_res = crate::spotify::playlist::set_playlist(&new_playlist_res.0.0, &track_uris, &token);
+    let set_res = client.set_playlist(&new_playlist_res.0.0, &track_uris);
     match set_res {
         Ok(_) => {
             println!("Playlist creation complete.\nURI: {}\nLink: {}", new_playlist_res.0, new_playlist_res.1);
         }
         Err(e) => {
-            println!("Failed to set playlist contents:\n{:?}", e);
-            return;
+            e.exit("Failed to set playlist contents");
         }
     }
 }
@@ -0,0 +1,129 @@
+// Passphrase based encryption for the secret bits of the config.
+// Only compiled in with the "encrypted_config" feature.
+
+use std::sync::Mutex;
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use thiserror::Error;
+
+/// Marks a value in the config as encrypted
+pub const PREFIX: &str = "enc:";
+pub const ENV_PASSPHRASE: &str = "SPOTIFY_CLI_PASSPHRASE";
+
+const NONCE_LEN: usize = 24;
+
+#[derive(Error, Debug)]
+pub enum CryptError {
+    #[error("Couldn't read passphrase: {0}")]
+    Passphrase(#[from] std::io::Error),
+    #[error("Passphrases didn't match")]
+    Confirm,
+    #[error("Failed to decrypt config, wrong passphrase?")]
+    Decrypt,
+    #[error("Encrypted value in the config is malformed")]
+    Malformed,
+    #[error("Failed to derive key: {0}")]
+    Kdf(String),
+}
+
+#[derive(Clone)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    pub fn derive(passphrase: &str, salt: &str) -> Result<Self, CryptError> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt.as_bytes(), &mut key)
+            .map_err(|e| CryptError::Kdf(e.to_string()))?;
+        Ok(Key(key))
+    }
+
+    pub fn seal(&self, plain: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).expect("Failed to gather randomness for nonce");
+
+        let sealed = XChaCha20Poly1305::new(&self.0.into())
+            .encrypt(XNonce::from_slice(&nonce), plain.as_bytes())
+            .expect("Encryption into a Vec can't fail");
+
+        let mut out = nonce.to_vec();
+        out.extend(sealed);
+        format!("{}{}", PREFIX, STANDARD.encode(out))
+    }
+
+    pub fn open(&self, value: &str) -> Result<String, CryptError> {
+        let data = value
+            .strip_prefix(PREFIX)
+            .and_then(|b64| STANDARD.decode(b64).ok())
+            .filter(|data| data.len() > NONCE_LEN)
+            .ok_or(CryptError::Malformed)?;
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+
+        let plain = XChaCha20Poly1305::new(&self.0.into())
+            .decrypt(XNonce::from_slice(nonce), sealed)
+            .map_err(|_| CryptError::Decrypt)?;
+        String::from_utf8(plain).map_err(|_| CryptError::Malformed)
+    }
+}
+
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+pub fn new_salt() -> String {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).expect("Failed to gather randomness for salt");
+    STANDARD.encode(salt)
+}
+
+// Config gets read a handful of times per run, nobody wants to type the passphrase each time
+static KEY: Mutex<Option<(String, Key)>> = Mutex::new(None);
+
+/// The key for `salt`, from SPOTIFY_CLI_PASSPHRASE or a prompt.
+/// `confirm` asks twice, for when we're about to encrypt with a new passphrase.
+pub fn key_for(salt: &str, confirm: bool) -> Result<Key, CryptError> {
+    let mut cached = KEY.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached_salt, key)) = cached.as_ref() {
+        if cached_salt == salt {
+            return Ok(key.clone());
+        }
+    }
+
+    let passphrase = match std::env::var(ENV_PASSPHRASE) {
+        Ok(passphrase) if !passphrase.is_empty() => passphrase,
+        _ => {
+            let passphrase = rpassword::prompt_password("Config passphrase: ")?;
+            if confirm && passphrase != rpassword::prompt_password("Again: ")? {
+                return Err(CryptError::Confirm);
+            }
+            passphrase
+        }
+    };
+
+    let key = Key::derive(&passphrase, salt)?;
+    *cached = Some((salt.to_owned(), key.clone()));
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open() {
+        let salt = new_salt();
+        let key = Key::derive("hunter2", &salt).unwrap();
+
+        let sealed = key.seal("refresh-token");
+        assert!(is_sealed(&sealed));
+        assert_ne!(sealed, key.seal("refresh-token"));
+        assert_eq!(key.open(&sealed).unwrap(), "refresh-token");
+
+        let wrong = Key::derive("hunter3", &salt).unwrap();
+        assert!(matches!(wrong.open(&sealed), Err(CryptError::Decrypt)));
+        assert!(matches!(key.open("enc:nope"), Err(CryptError::Malformed)));
+    }
+}
@@ -0,0 +1,182 @@
+// Where our files live. Follows the XDG base directory spec unless told otherwise:
+// the config in $XDG_CONFIG_HOME/spotify-cli, token caches in $XDG_STATE_HOME/spotify-cli.
+// Nothing goes in $XDG_CACHE_HOME: that's for files that can be deleted at any time, and
+// despite its name the token cache isn't one. Refresh tokens rotate, so it can hold the only
+// login that still works, which makes it state.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const ENV_CONFIG_DIR: &str = "SPOTIFY_CLI_CONFIG_DIR";
+
+const APP_DIR: &str = "spotify-cli";
+const CONFIG_FILE: &str = "config";
+const TOKEN_CACHE_FILE: &str = "token_cache";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Locations {
+    pub config_file: PathBuf,
+    /// Token cache of the default profile, other profiles get a ".<name>" suffix
+    pub token_cache: PathBuf,
+    /// Where token caches were kept before we followed XDG, if that's somewhere else
+    pub legacy_dir: Option<PathBuf>,
+}
+
+/// Resolves `--config` if given, then SPOTIFY_CLI_CONFIG_DIR, then the XDG directories.
+/// An explicit directory keeps everything together in it, as it always has.
+/// None if there's nothing to go on, not even a home directory.
+pub fn locate(arg: Option<&Path>, env: impl Fn(&str) -> Option<OsString>) -> Option<Locations> {
+    let env = |name: &str| env(name).filter(|value| !value.is_empty());
+    let home = env("HOME").or_else(|| env("USERPROFILE")).map(PathBuf::from);
+
+    // The environment variable always names a directory, --config can be either
+    let explicit = arg
+        .map(|path| (path.to_path_buf(), is_dir_like(path)))
+        .or_else(|| env(ENV_CONFIG_DIR).map(|dir| (dir.into(), true)));
+    if let Some((path, is_dir)) = explicit {
+        let path = expand_tilde(&path, home.as_deref());
+        if is_dir || path.is_dir() {
+            return Some(Locations {
+                config_file: path.join(CONFIG_FILE),
+                token_cache: path.join(TOKEN_CACHE_FILE),
+                legacy_dir: None,
+            });
+        }
+        // A config file of its own gets a token cache of its own
+        let mut token_cache = path.clone().into_os_string();
+        token_cache.push(".");
+        token_cache.push(TOKEN_CACHE_FILE);
+        return Some(Locations {
+            config_file: path,
+            token_cache: token_cache.into(),
+            legacy_dir: None,
+        });
+    }
+
+    // Relative XDG paths are invalid per the spec and should be ignored
+    let xdg = |name: &str, fallback: &str| {
+        env(name)
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| home.as_ref().map(|home| home.join(fallback)))
+            .map(|dir| dir.join(APP_DIR))
+    };
+    let config_dir = xdg("XDG_CONFIG_HOME", ".config")?;
+    let state_dir = xdg("XDG_STATE_HOME", ".local/state")?;
+
+    Some(Locations {
+        config_file: config_dir.join(CONFIG_FILE),
+        token_cache: state_dir.join(TOKEN_CACHE_FILE),
+        legacy_dir: (config_dir != state_dir).then_some(config_dir),
+    })
+}
+
+fn expand_tilde(path: &Path, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~"), home) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// An existing directory, or something that says it is one with a trailing slash
+fn is_dir_like(path: &Path) -> bool {
+    path.is_dir()
+        || path
+            .as_os_str()
+            .as_encoded_bytes()
+            .last()
+            .is_some_and(|&b| std::path::is_separator(b as char))
+}
+
+/// Moves token caches left next to the config by older versions into the state directory,
+/// so a rotated refresh token isn't lost. Anything that doesn't move is simply left behind.
+pub fn migrate_token_caches(locations: &Locations) {
+    let (Some(legacy_dir), Some(state_dir)) = (&locations.legacy_dir, locations.token_cache.parent()) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(legacy_dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let bytes = name.as_encoded_bytes();
+        let is_cache = bytes == TOKEN_CACHE_FILE.as_bytes()
+            || bytes.starts_with(format!("{}.", TOKEN_CACHE_FILE).as_bytes());
+        let target = state_dir.join(&name);
+        if is_cache && !target.exists() && create_private_dir(state_dir).is_ok() {
+            fs::rename(entry.path(), target).ok();
+        }
+    }
+}
+
+/// Creates `dir` and any missing parents, readable only by us
+pub fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<OsString> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| OsString::from(v))
+        }
+    }
+
+    #[test]
+    fn test_locate_xdg() {
+        let found = locate(None, env(&[("HOME", "/home/me")])).unwrap();
+        assert_eq!(found.config_file, Path::new("/home/me/.config/spotify-cli/config"));
+        assert_eq!(found.token_cache, Path::new("/home/me/.local/state/spotify-cli/token_cache"));
+        assert_eq!(found.legacy_dir.as_deref(), Some(Path::new("/home/me/.config/spotify-cli")));
+
+        let vars = [
+            ("HOME", "/home/me"),
+            ("XDG_CONFIG_HOME", "/xdg/config"),
+            ("XDG_STATE_HOME", "relative/is/ignored"),
+        ];
+        let found = locate(None, env(&vars)).unwrap();
+        assert_eq!(found.config_file, Path::new("/xdg/config/spotify-cli/config"));
+        assert_eq!(found.token_cache, Path::new("/home/me/.local/state/spotify-cli/token_cache"));
+
+        assert_eq!(locate(None, env(&[])), None);
+    }
+
+    #[test]
+    fn test_locate_explicit() {
+        let vars = [("HOME", "/home/me"), (ENV_CONFIG_DIR, "~/spotify")];
+        let found = locate(None, env(&vars)).unwrap();
+        assert_eq!(found.config_file, Path::new("/home/me/spotify/config"));
+        assert_eq!(found.token_cache, Path::new("/home/me/spotify/token_cache"));
+        assert_eq!(found.legacy_dir, None);
+
+        // --config wins over the environment, and can name a file
+        let found = locate(Some(Path::new("/etc/work.toml")), env(&vars)).unwrap();
+        assert_eq!(found.config_file, Path::new("/etc/work.toml"));
+        assert_eq!(found.token_cache, Path::new("/etc/work.toml.token_cache"));
+
+        let found = locate(Some(Path::new("/nonexistent/dir/")), env(&[])).unwrap();
+        assert_eq!(found.config_file, Path::new("/nonexistent/dir/config"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_locate_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = std::ffi::OsStr::from_bytes(b"/tmp/caf\xe9/");
+        let found = locate(Some(Path::new(dir)), env(&[])).unwrap();
+        assert_eq!(found.config_file.as_os_str().as_bytes(), b"/tmp/caf\xe9/config");
+    }
+}
@@ -0,0 +1,173 @@
+// Just enough HTTP/1.1 for our own little servers (the auth redirect listener, the test
+// server and the mock server): one request per connection, bodies sized by Content-Length.
+// The mock server includes this file with #[path], and no one binary uses all of it.
+#![allow(dead_code)]
+
+use std::io::{self, BufRead};
+
+use ureq::serde_json::{self, Value};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path and query as sent, like "/me/tracks?limit=50"
+    pub target: String,
+    /// Percent-decoded
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn read(reader: &mut impl BufRead) -> io::Result<Request> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request line"));
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let (method, path, query, target) = (method.to_owned(), percent_decode(path), parse_pairs(query), target.to_owned());
+
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+            }
+        }
+
+        let mut request = Request {
+            method,
+            target,
+            path,
+            query,
+            headers,
+            body: String::new(),
+        };
+        let length = request.header("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        request.body = String::from_utf8_lossy(&body).into_owned();
+        Ok(request)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers.iter().find(|(n, _)| *n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Every query parameter but `names`, in order
+    pub fn query_without(&self, names: &[&str]) -> Vec<(String, String)> {
+        self.query.iter().filter(|(n, _)| !names.contains(&n.as_str())).cloned().collect()
+    }
+
+    /// An application/x-www-form-urlencoded body
+    pub fn form(&self) -> Vec<(String, String)> {
+        parse_pairs(&self.body)
+    }
+
+    /// Null for an empty body, None if it isn't JSON
+    pub fn json(&self) -> Option<Value> {
+        match self.body.is_empty() {
+            true => Some(Value::Null),
+            false => serde_json::from_str(&self.body).ok(),
+        }
+    }
+}
+
+/// A full response to `status` (like "200 OK"), with a correct Content-Length. The connection
+/// gets closed after it, so ureq never tries to reuse one we've let go of.
+pub fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {}\r\n", status);
+    for (name, value) in headers {
+        response += &format!("{}: {}\r\n", name, value);
+    }
+    response + &format!("Content-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+}
+
+/// The name=value pairs of a query string or form body, decoded
+pub fn parse_pairs(text: &str) -> Vec<(String, String)> {
+    text.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Percent-decoding, with '+' as a space like forms and ureq's query strings have it
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => match text.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                Some(byte) => {
+                    out.push(byte);
+                    i += 2;
+                }
+                None => out.push(b'%'),
+            },
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+pub fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let mut text = "POST /a%20b?x=1&y=two+words HTTP/1.1\r\nContent-Length: 4\r\nHost: h\r\n\r\nbodyextra".as_bytes();
+        let request = Request::read(&mut text).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "/a%20b?x=1&y=two+words");
+        assert_eq!(request.path, "/a b");
+        assert_eq!(request.query("y"), Some("two words"));
+        assert_eq!(request.query_without(&["y"]), vec![("x".to_owned(), "1".to_owned())]);
+        assert_eq!(request.header("HOST"), Some("h"));
+        assert_eq!(request.body, "body");
+
+        assert!(Request::read(&mut "".as_bytes()).is_err());
+        assert!(Request::read(&mut "GET\r\n\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("user-top-read playlist-modify-public"), "user-top-read%20playlist-modify-public");
+        assert_eq!(percent_encode("http://localhost:8888/cb"), "http%3A%2F%2Flocalhost%3A8888%2Fcb");
+        assert_eq!(percent_decode(&percent_encode("a b/✓")), "a b/✓");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b+c"), "a b c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+        assert_eq!(percent_decode("%E2%9C%93"), "✓");
+    }
+}
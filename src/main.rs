@@ -1,4 +1,8 @@
 mod commands;
+#[cfg(feature = "encrypted_config")]
+mod crypt;
+mod dirs;
+mod http;
 mod spotify;
 mod util;
 
@@ -9,9 +13,28 @@ use clap::{Parser, Subcommand};
 #[derive(Parser)]
 #[command(author, version)]
 struct Cli {
-    #[arg(short, long, value_name = "FILE")]
-    #[arg(default_value = "~/.config/spotify-cli/")]
-    config: PathBuf,
+    /// Config directory, or the config file itself
+    /// [default: $SPOTIFY_CLI_CONFIG_DIR, or $XDG_CONFIG_HOME/spotify-cli]
+    #[arg(short, long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Print retries and other details to stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Save every API request and response as JSON files in DIR, with tokens redacted
+    #[cfg(feature = "cassettes")]
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer API requests from files saved with --record instead of asking Spotify
+    #[cfg(feature = "cassettes")]
+    #[arg(long, global = true, value_name = "DIR")]
+    replay: Option<PathBuf>,
+
+    #[arg(short, long, global = true)]
+    /// Profile in the config to use [default: default_profile from the config, or "default"]
+    profile: Option<String>,
 
     #[command(subcommand)]
     command: Commands,
@@ -27,11 +50,26 @@ enum Commands {
         #[command(subcommand)]
         user_command: UserCommands,
     },
-    Auth,
+    Auth {
+        #[command(subcommand)]
+        auth_command: Option<AuthSub>,
+
+        #[command(flatten)]
+        args: commands::auth::AuthArgs,
+    },
+    Config {
+        #[command(subcommand)]
+        config_command: ConfigSub,
+    },
     Debug {
         #[command(subcommand)]
         debug_command: DebugSub,
     },
+    /// Short names for playlists, usable anywhere a playlist is expected
+    Alias {
+        #[command(subcommand)]
+        alias_command: AliasSub,
+    },
 }
 
 #[derive(Subcommand)]
@@ -51,7 +89,7 @@ enum PlaylistCommands {
 
         #[arg(value_name = "Playlist 2")]
         #[arg(
-            long_help = "Either the URI of a playlist to copy from/into,\nor the name of a new playlist"
+            long_help = "Either a playlist (ID, URI, link or alias) to copy from/into,\nor the name of a new playlist"
         )]
         b: String,
     },
@@ -61,6 +99,15 @@ enum PlaylistCommands {
 
         #[arg(value_name = "Sorting method")]
         sort_method: Vec<commands::playlist::sort::SortMethod>,
+
+        /// What to do if someone else changes the playlist while it's being sorted
+        #[arg(long, value_enum, default_value_t)]
+        on_conflict: commands::playlist::OnConflict,
+
+        /// Replace the whole playlist instead of moving items around, which resets every item's date added.
+        /// Someone else's edits are only caught up to just before the write, not during it
+        #[arg(long)]
+        rewrite: bool,
     },
     New {
         #[arg(value_name = "Name")]
@@ -91,12 +138,87 @@ enum UserCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum AuthSub {
+    /// Lists profiles and whether their tokens work
+    List,
+    /// Shows the logged in user, granted scopes and token expiry
+    Status,
+    /// Removes the profile's tokens
+    Logout,
+}
+
+#[derive(Subcommand)]
+enum ConfigSub {
+    /// Creates the config directory and file, asking for the app's credentials
+    Init,
+    /// Prints one value from the selected profile
+    Get {
+        #[arg(value_name = "Key")]
+        key: String,
+    },
+    /// Sets one value in the selected profile
+    Set {
+        #[arg(value_name = "Key")]
+        key: String,
+
+        #[arg(value_name = "Value", required_unless_present("unset"))]
+        value: Option<String>,
+
+        /// Remove the key instead
+        #[arg(long, conflicts_with("value"))]
+        unset: bool,
+    },
+    /// Checks the config file for problems
+    Validate,
+    /// Prints the path of the config file
+    Path,
+    /// Prints the selected profile's config
+    Show {
+        /// Apply SPOTIFY_CLI_* environment overrides and say where each value came from
+        #[arg(long)]
+        resolved: bool,
+    },
+    /// Encrypts the client secret and tokens with a passphrase
+    ///
+    /// The passphrase is read from SPOTIFY_CLI_PASSPHRASE, or prompted for
+    #[cfg(feature = "encrypted_config")]
+    Encrypt,
+    /// Removes the passphrase, storing secrets in plaintext again
+    #[cfg(feature = "encrypted_config")]
+    Decrypt,
+}
+
+#[derive(Subcommand)]
+enum AliasSub {
+    /// Adds an alias, replacing any existing one with the same name
+    Add {
+        #[arg(value_name = "Name")]
+        name: String,
+
+        /// ID, URI or link of the playlist, another alias, or the name of one of your playlists
+        #[arg(value_name = "Playlist")]
+        playlist: String,
+    },
+    /// Removes an alias
+    Remove {
+        #[arg(value_name = "Name")]
+        name: String,
+    },
+    /// Lists the selected profile's aliases
+    List,
+}
+
 #[derive(Subcommand)]
 enum DebugSub {
     /// Dumps the contents of a playlist to stdout
     DumpPlaylist {
         #[arg(value_name = "Playlist")]
         playlist: String,
+
+        /// Only items added by this user (their user ID)
+        #[arg(long, value_name = "User")]
+        added_by: Option<String>,
     },
     /// Empties the contents of a playlist
     ClearPlaylist {
@@ -107,40 +229,96 @@ enum DebugSub {
 
 fn main() {
     let cli = Cli::parse();
+    util::set_verbose(cli.verbose);
+    #[cfg(feature = "cassettes")]
+    {
+        use spotify::cassette::{self, Cassette};
+        let cassette = match (&cli.record, &cli.replay) {
+            (Some(dir), _) => Some(Cassette::record(dir)),
+            (_, Some(dir)) => Some(Cassette::replay(dir)),
+            _ => None,
+        };
+        match cassette {
+            Some(Ok(cassette)) => cassette::set(cassette),
+            Some(Err(e)) => {
+                eprintln!("Can't use the cassette: {}", e);
+                std::process::exit(spotify::error::EXIT_FAILURE);
+            }
+            None => {}
+        }
+    }
+    let locations = match dirs::locate(cli.config.as_deref(), |name| std::env::var_os(name)) {
+        Some(locations) => locations,
+        None => {
+            eprintln!(
+                "Couldn't find a home directory to keep the config in, pass --config or set {}",
+                dirs::ENV_CONFIG_DIR
+            );
+            std::process::exit(util::ConfigError::EXIT_CODE);
+        }
+    };
+    dirs::migrate_token_caches(&locations);
+    let config = util::ConfigPath {
+        file: locations.config_file,
+        token_cache: locations.token_cache,
+        profile: cli.profile,
+    };
 
     match &cli.command {
         Commands::Playlist { playlist_command } => match playlist_command {
             PlaylistCommands::Copy { a, prep, b } => {
-                commands::playlist::copy::run(cli.config, a, prep, b);
+                commands::playlist::copy::run(config, a, prep, b);
             }
             PlaylistCommands::Sort {
                 playlist,
                 sort_method,
+                on_conflict,
+                rewrite,
             } => {
-                commands::playlist::sort::run(cli.config, playlist, sort_method);
+                commands::playlist::sort::run(config, playlist, sort_method, *on_conflict, *rewrite);
             }
             PlaylistCommands::New { name } => {
-                commands::playlist::new::run(cli.config, name);
+                commands::playlist::new::run(config, name);
             }
         },
         Commands::User { user_command } => match user_command {
             UserCommands::TopTracks { term, dump, name, number } => {
-                commands::user::top_tracks::run(cli.config, term, *number, name.as_deref(), *dump)
+                commands::user::top_tracks::run(config, term, *number, name.as_deref(), *dump)
             }
         }
-        Commands::Auth => {
-            commands::auth::run(cli.config);
-        }
+        Commands::Auth { auth_command, args } => match auth_command {
+            None => commands::auth::run(config, args),
+            Some(AuthSub::List) => commands::auth::list(config),
+            Some(AuthSub::Status) => commands::auth::status(config),
+            Some(AuthSub::Logout) => commands::auth::logout(config),
+        },
+        Commands::Config { config_command } => match config_command {
+            ConfigSub::Init => commands::config::init(config),
+            ConfigSub::Get { key } => commands::config::get(config, key),
+            ConfigSub::Set { key, value, .. } => commands::config::set(config, key, value.as_deref()),
+            ConfigSub::Validate => commands::config::validate(config),
+            ConfigSub::Path => commands::config::path(config),
+            ConfigSub::Show { resolved } => commands::config::show(config, *resolved),
+            #[cfg(feature = "encrypted_config")]
+            ConfigSub::Encrypt => commands::config::encrypt(config),
+            #[cfg(feature = "encrypted_config")]
+            ConfigSub::Decrypt => commands::config::decrypt(config),
+        },
         Commands::Debug { debug_command } => {
             // Match the debug subcommands
             match debug_command {
-                DebugSub::DumpPlaylist { playlist } => {
-                    commands::debug::dump_playlist(cli.config, playlist);
+                DebugSub::DumpPlaylist { playlist, added_by } => {
+                    commands::debug::dump_playlist(config, playlist, added_by.as_deref());
                 }
                 DebugSub::ClearPlaylist { playlist } => {
-                    commands::debug::clear_playlist(cli.config, playlist);
+                    commands::debug::clear_playlist(config, playlist);
                 }
             }
         }
+        Commands::Alias { alias_command } => match alias_command {
+            AliasSub::Add { name, playlist } => commands::alias::add(config, name, playlist),
+            AliasSub::Remove { name } => commands::alias::remove(config, name),
+            AliasSub::List => commands::alias::list(config),
+        },
     }
 }
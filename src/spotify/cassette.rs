@@ -0,0 +1,347 @@
+// Records API traffic into a directory of JSON files and plays it back, so commands can be
+// tested (and bugs reproduced) without a Spotify account. Only built with the cassettes feature.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use ureq::serde_json::{self, Value};
+
+use crate::spotify::Error;
+
+/// What every secret is replaced with, and the token used while replaying
+pub const REDACTED: &str = "REDACTED";
+
+/// Never written to a cassette, wherever they turn up in a body
+const SECRET_KEYS: &[&str] = &["access_token", "refresh_token", "client_secret", "code_verifier"];
+
+/// The rest of the response headers are noise that would only make cassettes harder to read
+const KEPT_HEADERS: &[&str] = &["Content-Type", "Retry-After"];
+
+/// Stands in for the base URL in recorded bodies, so the links Spotify hands back (like the
+/// `next` of a page) lead to wherever the cassette is replayed against
+const BASE_URL: &str = "{api_url}";
+
+static CURRENT: OnceLock<Arc<Cassette>> = OnceLock::new();
+
+/// Set once from --record or --replay, every client made afterwards goes through it
+pub fn set(cassette: Cassette) {
+    CURRENT.set(Arc::new(cassette)).ok();
+}
+
+pub fn current() -> Option<Arc<Cassette>> {
+    CURRENT.get().cloned()
+}
+
+/// Replaying needs no login, since nothing goes over the wire
+pub fn replaying() -> bool {
+    CURRENT.get().is_some_and(|cassette| cassette.is_replay())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+/// A directory holding one numbered file per request, in the order they were made.
+///
+/// Request headers aren't kept at all, so the access token never lands on disk.
+pub struct Cassette {
+    mode: Mode,
+    dir: PathBuf,
+    /// Number of the last interaction
+    count: Mutex<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct RecordedRequest {
+    method: String,
+    /// Relative to the base URL. Together with BASE_URL in the response bodies, that lets a
+    /// cassette be replayed against any api_url.
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RecordedResponse {
+    status: u16,
+    status_text: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    /// JSON bodies are kept as JSON so they're readable (and editable), anything else as a string
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    body: Value,
+}
+
+impl Cassette {
+    /// Refuses a directory with files in it, so a new recording can't get mixed up with an old one
+    pub fn record(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        if fs::read_dir(dir)?.next().is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} isn't empty", dir.display()),
+            ));
+        }
+        Ok(Cassette::new(Mode::Record, dir))
+    }
+
+    pub fn replay(dir: &Path) -> io::Result<Self> {
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} isn't a directory", dir.display()),
+            ));
+        }
+        Ok(Cassette::new(Mode::Replay, dir))
+    }
+
+    fn new(mode: Mode, dir: &Path) -> Self {
+        Cassette {
+            mode,
+            dir: dir.to_path_buf(),
+            count: Mutex::new(0),
+        }
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.mode == Mode::Replay
+    }
+
+    /// Sends `request` and records the exchange, or answers it from the next recorded one
+    pub fn send(&self, request: ureq::Request, body: Option<&Value>, base_url: &str) -> Result<ureq::Response, Error> {
+        let mut count = self.count.lock().unwrap();
+        *count += 1;
+        let file = self.dir.join(format!("{:04}.json", *count));
+
+        let recorded = RecordedRequest {
+            method: request.method().to_owned(),
+            url: request.url().strip_prefix(base_url).unwrap_or(request.url()).to_owned(),
+            body: body.cloned().map(scrub),
+        };
+
+        let response = match self.mode {
+            Mode::Replay => {
+                let data = fs::read_to_string(&file)
+                    .map_err(|e| Error::Cassette(format!("Can't read {}: {}", file.display(), e)))?;
+                let interaction: Interaction = serde_json::from_str(&data)
+                    .map_err(|e| Error::Cassette(format!("Can't parse {}: {}", file.display(), e)))?;
+                if interaction.request != recorded {
+                    return Err(Error::Cassette(format!(
+                        "{} has {} {}, but this is {} {}",
+                        file.display(),
+                        interaction.request.method,
+                        interaction.request.url,
+                        recorded.method,
+                        recorded.url
+                    )));
+                }
+                interaction.response
+            }
+            Mode::Record => {
+                let res = match body {
+                    Some(body) => request.send_json(body),
+                    None => request.call(),
+                };
+                let response = match res {
+                    Ok(response) | Err(ureq::Error::Status(_, response)) => RecordedResponse::read(response, base_url)?,
+                    Err(e) => return Err(Error::from(e)),
+                };
+
+                let interaction = Interaction {
+                    request: recorded,
+                    response,
+                };
+                let data = serde_json::to_string_pretty(&interaction).map_err(io::Error::from)?;
+                fs::write(&file, data + "\n")
+                    .map_err(|e| Error::Cassette(format!("Can't write {}: {}", file.display(), e)))?;
+                interaction.response
+            }
+        };
+
+        response.into_response(base_url)
+    }
+}
+
+impl RecordedResponse {
+    fn read(response: ureq::Response, base_url: &str) -> io::Result<Self> {
+        let status = response.status();
+        let status_text = response.status_text().to_owned();
+        let headers = KEPT_HEADERS
+            .iter()
+            .filter_map(|&name| Some((name.to_owned(), response.header(name)?.to_owned())))
+            .collect();
+
+        let text = response.into_string()?;
+        let body = match serde_json::from_str(&text) {
+            Ok(json) => rebase(scrub(json), base_url, BASE_URL),
+            Err(_) if text.is_empty() => Value::Null,
+            Err(_) => Value::String(text),
+        };
+
+        Ok(RecordedResponse {
+            status,
+            status_text,
+            headers,
+            body,
+        })
+    }
+
+    /// Turned back into what ureq would have given us, errors included
+    fn into_response(self, base_url: &str) -> Result<ureq::Response, Error> {
+        let body = match self.body {
+            Value::Null => String::new(),
+            Value::String(text) => text,
+            json => rebase(json, BASE_URL, base_url).to_string(),
+        };
+        let mut text = format!("HTTP/1.1 {} {}\r\n", self.status, self.status_text);
+        for (name, value) in &self.headers {
+            text += &format!("{}: {}\r\n", name, value);
+        }
+        text += &format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+
+        let response: ureq::Response = text
+            .parse()
+            .map_err(|e| Error::Cassette(format!("Bad recorded response: {}", e)))?;
+        match self.status {
+            status if status >= 400 => Err(Error::from(ureq::Error::Status(status, response))),
+            _ => Ok(response),
+        }
+    }
+}
+
+fn scrub(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| match SECRET_KEYS.contains(&key.as_str()) {
+                    true => (key, Value::String(REDACTED.to_owned())),
+                    false => (key, scrub(value)),
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(scrub).collect()),
+        value => value,
+    }
+}
+
+/// Swaps the `from` at the start of any string in `value` for `to`
+fn rebase(value: Value, from: &str, to: &str) -> Value {
+    match value {
+        Value::String(text) => match text.strip_prefix(from) {
+            Some(rest) => Value::String(format!("{}{}", to, rest)),
+            None => Value::String(text),
+        },
+        Value::Object(map) => Value::Object(map.into_iter().map(|(key, value)| (key, rebase(value, from, to))).collect()),
+        Value::Array(values) => Value::Array(values.into_iter().map(|value| rebase(value, from, to)).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::test_server::{respond, respond_with, TestServer};
+    use crate::spotify::types::Token;
+    use crate::spotify::SpotifyClient;
+    use crate::util;
+
+    fn client(url: &str, cassette: Cassette) -> SpotifyClient {
+        let config = util::Config {
+            api_url: Some(url.to_owned()),
+            max_retries: Some(1),
+            ..Default::default()
+        };
+        let mut client = SpotifyClient::new(Token::user("secret-token".to_owned()), &config);
+        client.cassette = Some(Arc::new(cassette));
+        client
+    }
+
+    fn profile() -> String {
+        respond_with(
+            "200 OK",
+            &[("Content-Type", "application/json")],
+            r#"{"id":"me","access_token":"should-vanish"}"#,
+        )
+    }
+
+    fn too_many() -> String {
+        respond_with("429 Too Many Requests", &[("Retry-After", "0")], "")
+    }
+
+    /// The first of two pages, linking to the second on `url`
+    fn first_page(url: &str) -> String {
+        respond("200 OK", &format!(r#"{{"items":[1,2],"next":"{}/me/tracks?offset=2&limit=2"}}"#, url))
+    }
+
+    type Run = (String, Result<ureq::Response, Error>, Result<ureq::Response, Error>, Result<Vec<u32>, Error>);
+
+    /// Every request the test makes, in order
+    fn run(client: &SpotifyClient) -> Run {
+        let profile = client.call(client.get("/me")).unwrap().into_string().unwrap();
+        let added = client.send_json(client.post("/playlists/abc/tracks"), ureq::json!({"uris": ["spotify:track:1"]}));
+        let missing = client.call(client.get("/playlists/nope"));
+        let paged = client.paginate(client.get("/me/tracks")).collect();
+        (profile, added, missing, paged)
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let dir = std::env::temp_dir().join(format!("spotify-cli-cassette-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+
+        let server = TestServer::bind();
+        server.answer(vec![
+            profile(),
+            too_many(),
+            respond("201 Created", r#"{"snapshot_id":"snapshot1"}"#),
+            respond("404 Not Found", r#"{"error":{"status":404,"message":"Not found"}}"#),
+            first_page(&server.url),
+            respond("200 OK", r#"{"items":[3],"next":null}"#),
+        ]);
+        let (profile, added, missing, paged) = run(&client(&server.url, Cassette::record(&dir).unwrap()));
+        assert_eq!(profile, r#"{"access_token":"REDACTED","id":"me"}"#);
+        assert_eq!(added.unwrap().status(), 201);
+        assert!(matches!(missing, Err(Error::NotFound { .. })));
+        assert_eq!(paged.unwrap(), vec![1, 2, 3]);
+
+        // The retry is in there too, and nothing secret is
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 6);
+        let all: String = (1..=6)
+            .map(|n| fs::read_to_string(dir.join(format!("{:04}.json", n))).unwrap())
+            .collect();
+        assert!(!all.contains("secret-token"));
+        assert!(!all.contains("should-vanish"));
+        assert!(all.contains("\"url\": \"/playlists/abc/tracks\""));
+        assert!(all.contains("spotify:track:1"));
+        assert!(!all.contains(&server.url));
+        assert!(all.contains("\"next\": \"{api_url}/me/tracks?offset=2&limit=2\""));
+        assert!(Cassette::record(&dir).is_err());
+
+        // Nothing is listening anymore, and a different base URL doesn't matter
+        let (profile, added, missing, paged) = run(&client("http://127.0.0.1:1", Cassette::replay(&dir).unwrap()));
+        assert_eq!(profile, r#"{"access_token":"REDACTED","id":"me"}"#);
+        assert_eq!(added.unwrap().into_string().unwrap(), r#"{"snapshot_id":"snapshot1"}"#);
+        assert!(matches!(missing, Err(Error::NotFound { message }) if message == "Not found"));
+        // The second page is asked for under the new base URL, as it was recorded
+        assert_eq!(paged.unwrap(), vec![1, 2, 3]);
+
+        // Asking for something else than what was recorded is an error, not a guess
+        let client = client("http://127.0.0.1:1", Cassette::replay(&dir).unwrap());
+        assert!(matches!(client.call(client.get("/me/tracks")), Err(Error::Cassette(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Something went wrong talking to Spotify. Each kind of failure exits with its own code,
+/// so scripts can tell "log in again" from "try again later".
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Spotify didn't accept our credentials: {message}\nRun auth again if this keeps happening.")]
+    Auth { message: String },
+    #[error("Not allowed: {message}")]
+    Permission { message: String },
+    #[error("Not found: {message}")]
+    NotFound { message: String },
+    #[error("Rate limited by Spotify{}", .retry_after.map(|s| format!(", try again in {}s", s)).unwrap_or_default())]
+    RateLimited { retry_after: Option<u64> },
+    #[error("Spotify returned {status}: {message}")]
+    Api { status: u16, message: String },
+    #[error("Couldn't reach Spotify: {0}")]
+    Network(Box<ureq::Transport>),
+    #[error("Unexpected response from Spotify: {0}")]
+    Decode(#[from] std::io::Error),
+    #[error("This needs a user login, but we only have an app token. Run auth first.")]
+    NeedsUser,
+    #[error("Can't get a token: {0}")]
+    Credentials(String),
+    #[error("Writing items {first} to {last} (chunk {chunk} of {chunks}) failed, so the playlist has the items before them, and these may or may not have gone in: {source}")]
+    WriteFailed {
+        chunk: usize,
+        chunks: usize,
+        first: usize,
+        last: usize,
+        source: Box<Error>,
+    },
+    #[error("The playlist should have {expected} items after writing, but it has {found}")]
+    CountMismatch { expected: usize, found: usize },
+    #[error("Playlists can't hold more than {} items, this would be {count}", crate::spotify::playlist::MAX_PLAYLIST_ITEMS)]
+    TooManyItems { count: usize },
+    #[error("Move {n} of {moves} failed, so the playlist is only partly reordered: {source}")]
+    MoveFailed { n: usize, moves: usize, source: Box<Error> },
+    #[error("The new order doesn't have the same items as the playlist")]
+    NotAReordering,
+    #[error("The playlist changed since it was read (someone else edited it?)")]
+    PlaylistChanged,
+    #[error(
+        "Rewriting would drop local files and unavailable tracks (like {}), sort without --rewrite to keep them",
+        uri.as_ref().map_or("one that was removed from Spotify", |uri| uri.0.as_str())
+    )]
+    Unwritable { uri: Option<crate::spotify::types::Uri> },
+    #[cfg(feature = "cassettes")]
+    #[error("Cassette: {0}")]
+    Cassette(String),
+}
+
+pub const EXIT_FAILURE: i32 = 1;
+// 2 is what clap exits with for bad arguments
+pub const EXIT_AUTH: i32 = 3;
+pub const EXIT_PERMISSION: i32 = 4;
+pub const EXIT_NOT_FOUND: i32 = 5;
+pub const EXIT_RATE_LIMITED: i32 = 6;
+pub const EXIT_NETWORK: i32 = 7;
+pub const EXIT_CONFLICT: i32 = 8;
+
+impl Error {
+    fn from_status(status: u16, message: String, retry_after: Option<u64>) -> Self {
+        match status {
+            401 => Error::Auth { message },
+            403 => Error::Permission { message },
+            404 => Error::NotFound { message },
+            429 => Error::RateLimited { retry_after },
+            status => Error::Api { status, message },
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Auth { .. } | Error::NeedsUser | Error::Credentials(_) => EXIT_AUTH,
+            Error::Permission { .. } => EXIT_PERMISSION,
+            Error::NotFound { .. } => EXIT_NOT_FOUND,
+            Error::RateLimited { .. } => EXIT_RATE_LIMITED,
+            Error::Network(_) => EXIT_NETWORK,
+            Error::PlaylistChanged => EXIT_CONFLICT,
+            Error::WriteFailed { source, .. } | Error::MoveFailed { source, .. } => source.exit_code(),
+            Error::Api { .. } | Error::Decode(_) | Error::CountMismatch { .. } | Error::TooManyItems { .. } => EXIT_FAILURE,
+            Error::NotAReordering | Error::Unwritable { .. } => EXIT_FAILURE,
+            #[cfg(feature = "cassettes")]
+            Error::Cassette(_) => EXIT_FAILURE,
+        }
+    }
+
+    /// A 401 that a new access token would fix, as opposed to a revoked or bogus one
+    pub fn is_expired_token(&self) -> bool {
+        matches!(self, Error::Auth { message } if message.contains("expired"))
+    }
+
+    /// Prints "`doing`: what went wrong" to stderr and exits with the matching code
+    pub fn exit(&self, doing: &str) -> ! {
+        eprintln!("{}: {}", doing, self);
+        std::process::exit(self.exit_code())
+    }
+
+    /// The accounts service says 400 for a bad refresh token or client, which is an auth problem
+    pub fn from_token_endpoint(e: ureq::Error) -> Self {
+        match Error::from(e) {
+            Error::Api { status: 400, message } => Error::Auth { message },
+            e => e,
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(status, response) => {
+                let retry_after = response.header("Retry-After").and_then(|s| s.trim().parse().ok());
+                let status_text = response.status_text().to_owned();
+                let message = response
+                    .into_string()
+                    .ok()
+                    .and_then(|body| error_message(&body))
+                    .unwrap_or(status_text);
+                Error::from_status(status, message, retry_after)
+            }
+            ureq::Error::Transport(transport) => Error::Network(Box::new(transport)),
+        }
+    }
+}
+
+/// The Web API sends `{"error":{"status":401,"message":"..."}}`,
+/// the accounts service `{"error":"invalid_grant","error_description":"..."}`
+fn error_message(body: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct ApiError {
+        message: String,
+    }
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Body {
+        Api {
+            error: ApiError,
+        },
+        Accounts {
+            error: String,
+            error_description: Option<String>,
+        },
+    }
+
+    match ureq::serde_json::from_str(body).ok()? {
+        Body::Api { error } => Some(error.message),
+        Body::Accounts {
+            error_description: Some(description),
+            ..
+        } => Some(description),
+        Body::Accounts { error, .. } => Some(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(code: u16, body: &str, headers: &str) -> Error {
+        let response = format!("HTTP/1.1 {} Whatever\r\n{}Content-Length: {}\r\n\r\n{}", code, headers, body.len(), body);
+        let response: ureq::Response = response.parse().unwrap();
+        Error::from(ureq::Error::Status(code, response))
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let e = status(401, r#"{"error":{"status":401,"message":"The access token expired"}}"#, "");
+        assert!(matches!(&e, Error::Auth { message } if message == "The access token expired"));
+        assert_eq!(e.exit_code(), EXIT_AUTH);
+
+        let e = status(403, r#"{"error":{"status":403,"message":"Insufficient client scope"}}"#, "");
+        assert!(matches!(&e, Error::Permission { message } if message == "Insufficient client scope"));
+
+        let e = status(404, "not json", "");
+        assert!(matches!(&e, Error::NotFound { message } if message == "Whatever"));
+        assert_eq!(e.exit_code(), EXIT_NOT_FOUND);
+
+        let e = status(429, "", "Retry-After: 30\r\n");
+        assert!(matches!(e, Error::RateLimited { retry_after: Some(30) }));
+        assert_eq!(e.to_string(), "Rate limited by Spotify, try again in 30s");
+        let e = status(429, "", "Retry-After: Wed, 21 Oct 2015 07:28:00 GMT\r\n");
+        assert!(matches!(e, Error::RateLimited { retry_after: None }));
+
+        let e = status(502, r#"{"error":{"status":502,"message":"Bad gateway"}}"#, "");
+        assert!(matches!(e, Error::Api { status: 502, .. }));
+        assert_eq!(e.exit_code(), EXIT_FAILURE);
+
+        let body = r#"{"error":"invalid_grant","error_description":"Refresh token revoked"}"#;
+        let response = format!("HTTP/1.1 400 Bad Request\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let e = Error::from_token_endpoint(ureq::Error::Status(400, response.parse().unwrap()));
+        assert!(matches!(&e, Error::Auth { message } if message == "Refresh token revoked"));
+    }
+}
@@ -1,5 +1,340 @@
 pub mod types;
+#[cfg(feature = "cassettes")]
+pub mod cassette;
+pub mod error;
+pub mod paging;
 pub mod playlist;
+pub mod reorder;
+pub mod search;
+#[cfg(test)]
+pub mod test_server;
 pub mod user;
 
-const BASE_URL: &str = "https://api.spotify.com/v1";
+use std::cell::RefCell;
+use std::time::Duration;
+
+use serde::Serialize;
+use ureq::serde_json::Value;
+
+use crate::spotify::types::{Token, TokenKind};
+use crate::util;
+
+pub use error::Error;
+
+pub const BASE_URL: &str = "https://api.spotify.com/v1";
+
+const USER_AGENT: &str = concat!("spotify-cli/", env!("CARGO_PKG_VERSION"));
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many times a request is retried when the config doesn't say
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+/// First backoff for server errors, doubling with each retry
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+/// Longer than this and we'd rather fail than leave the user staring at nothing
+const MAX_WAIT: Duration = Duration::from_secs(120);
+
+/// Gets a new access token when the one a client holds expires partway through a command
+pub type Refresh = Box<dyn Fn() -> Result<Token, Error>>;
+
+/// Everything needed to talk to the Web API. The API calls themselves are methods
+/// on this, spread across the modules for the parts of the API they cover.
+pub struct SpotifyClient {
+    agent: ureq::Agent,
+    token: RefCell<Token>,
+    refresh: Option<Refresh>,
+    base_url: String,
+    max_retries: u32,
+    #[cfg(feature = "cassettes")]
+    cassette: Option<std::sync::Arc<cassette::Cassette>>,
+}
+
+impl SpotifyClient {
+    /// Takes the base URL and retry budget from `config`, the base URL replacing
+    /// the real API (say with a mock server) if it's set
+    pub fn new(token: Token, config: &util::Config) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout(TIMEOUT)
+            .user_agent(USER_AGENT)
+            .build();
+
+        SpotifyClient {
+            agent,
+            token: RefCell::new(token),
+            refresh: None,
+            base_url: config.api_url.as_deref().unwrap_or(BASE_URL).trim_end_matches('/').to_owned(),
+            max_retries: config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            #[cfg(feature = "cassettes")]
+            cassette: cassette::current(),
+        }
+    }
+
+    /// Lets the client replace its token once it expires, instead of failing with a 401
+    pub fn with_refresh(mut self, refresh: Refresh) -> Self {
+        self.refresh = Some(refresh);
+        self
+    }
+
+    fn bearer(&self) -> String {
+        format!("Bearer {}", self.token.borrow().0)
+    }
+
+    /// A request to `path` under the base URL, already carrying the token
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        self.request_url(method, &format!("{}{}", self.base_url, path))
+    }
+
+    /// For URLs Spotify hands us whole, like the `next` of a page
+    fn request_url(&self, method: &str, url: &str) -> ureq::Request {
+        self.agent
+            .request(method, url)
+            .set("Authorization", &self.bearer())
+    }
+
+    fn get_url(&self, url: &str) -> ureq::Request {
+        self.request_url("GET", url)
+    }
+
+    fn get(&self, path: &str) -> ureq::Request {
+        self.request("GET", path)
+    }
+
+    fn post(&self, path: &str) -> ureq::Request {
+        self.request("POST", path)
+    }
+
+    fn put(&self, path: &str) -> ureq::Request {
+        self.request("PUT", path)
+    }
+
+    fn call(&self, request: ureq::Request) -> Result<ureq::Response, Error> {
+        self.refreshing(request, None)
+    }
+
+    fn send_json(&self, request: ureq::Request, body: impl Serialize) -> Result<ureq::Response, Error> {
+        let body = ureq::serde_json::to_value(body).map_err(std::io::Error::from)?;
+        self.refreshing(request, Some(&body))
+    }
+
+    /// Sends `request`, and if the token expired along the way (a long sort can outlive it)
+    /// refreshes it and sends the request once more with the new one
+    fn refreshing(&self, request: ureq::Request, body: Option<&Value>) -> Result<ureq::Response, Error> {
+        let res = self.retrying(&request, body);
+        let refresh = match (&res, &self.refresh) {
+            (Err(e), Some(refresh)) if e.is_expired_token() => refresh,
+            _ => return res,
+        };
+
+        if util::verbose() {
+            eprintln!("Access token expired, refreshing");
+        }
+        *self.token.borrow_mut() = refresh()?;
+        let request = request.set("Authorization", &self.bearer());
+        self.retrying(&request, body)
+    }
+
+    /// Sends until we get something other than a rate limit or a flaky server error,
+    /// or until the retry budget runs out
+    fn retrying(&self, request: &ureq::Request, body: Option<&Value>) -> Result<ureq::Response, Error> {
+        let mut attempt = 0;
+        loop {
+            let e = match self.send(request.clone(), body) {
+                Err(e) if attempt < self.max_retries => e,
+                res => return res,
+            };
+            let wait = match &e {
+                Error::RateLimited { retry_after: Some(seconds) } => Duration::from_secs(*seconds),
+                Error::RateLimited { retry_after: None } => backoff(attempt, random()),
+                // The write may have gone through before the error, and sending an append or a
+                // move again would do it twice. Their callers report them as failed instead.
+                Error::Api { status: 500 | 502 | 503, .. } if is_idempotent(request, body) => backoff(attempt, random()),
+                _ => return Err(e),
+            };
+            if wait > MAX_WAIT {
+                return Err(e);
+            }
+
+            attempt += 1;
+            if util::verbose() {
+                eprintln!(
+                    "{} {} failed ({}), retrying in {:.1}s ({}/{})",
+                    request.method(),
+                    request.url(),
+                    e,
+                    wait.as_secs_f32(),
+                    attempt,
+                    self.max_retries
+                );
+            }
+            #[cfg(feature = "cassettes")]
+            if self.cassette.as_ref().is_some_and(|cassette| cassette.is_replay()) {
+                // The wait already happened while recording
+                continue;
+            }
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// A single attempt at `request`, answered by the cassette instead if there is one
+    fn send(&self, request: ureq::Request, body: Option<&Value>) -> Result<ureq::Response, Error> {
+        #[cfg(feature = "cassettes")]
+        if let Some(cassette) = &self.cassette {
+            return cassette.send(request, body, &self.base_url);
+        }
+
+        let res = match body {
+            Some(body) => request.send_json(body),
+            None => request.call(),
+        };
+        res.map_err(Error::from)
+    }
+
+    /// Fails early for endpoints that need a user, rather than letting Spotify send back a bare 401/403
+    fn require_user(&self) -> Result<(), Error> {
+        match self.token.borrow().1 {
+            TokenKind::User => Ok(()),
+            TokenKind::App => Err(Error::NeedsUser),
+        }
+    }
+}
+
+/// Whether sending `request` twice leaves things as sending it once would. Reads are, and so
+/// is replacing a playlist's items. Adding to a playlist, creating one, or moving a range of
+/// items isn't. A 429 means nothing was done, so those are retried either way.
+fn is_idempotent(request: &ureq::Request, body: Option<&Value>) -> bool {
+    match request.method() {
+        "GET" | "HEAD" => true,
+        "PUT" => body.is_some_and(|body| body.get("range_start").is_none()),
+        _ => false,
+    }
+}
+
+/// Exponential backoff with jitter, somewhere between half and all of BACKOFF_BASE * 2^attempt,
+/// so a bunch of us backing off at once don't all come back at once
+fn backoff(attempt: u32, random: u32) -> Duration {
+    let max = BACKOFF_BASE.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_WAIT);
+    let jitter = max.mul_f64(random as f64 / u32::MAX as f64) / 2;
+    max / 2 + jitter
+}
+
+fn random() -> u32 {
+    let mut bytes = [0u8; 4];
+    getrandom::getrandom(&mut bytes).ok();
+    u32::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::spotify::test_server::{respond, respond_with, serve, TestServer};
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0, 0), Duration::from_millis(250));
+        assert_eq!(backoff(0, u32::MAX), Duration::from_millis(500));
+        assert_eq!(backoff(3, u32::MAX), Duration::from_secs(4));
+        assert!(backoff(3, u32::MAX / 2) > Duration::from_secs(2));
+        assert_eq!(backoff(40, u32::MAX), MAX_WAIT);
+    }
+
+    fn too_many() -> String {
+        respond_with("429 Too Many Requests", &[("Retry-After", "0")], "")
+    }
+
+    fn ok() -> String {
+        respond("200 OK", "{}")
+    }
+
+    /// The bearer token each request was sent with
+    fn tokens(server: &TestServer) -> Vec<String> {
+        server
+            .requests()
+            .iter()
+            .filter_map(|r| r.header("Authorization")?.strip_prefix("Bearer ").map(str::to_owned))
+            .collect()
+    }
+
+    #[test]
+    fn test_retries() {
+        let server = serve(vec![too_many(), too_many(), ok()]);
+        let spotify = server.client(2);
+        let res = spotify.call(spotify.get("/me"));
+        assert_eq!(res.unwrap().status(), 200);
+
+        let server = serve(vec![too_many(), too_many()]);
+        let spotify = server.client(1);
+        let res = spotify.call(spotify.get("/me"));
+        assert!(matches!(res, Err(Error::RateLimited { retry_after: Some(0) })));
+
+        let server = serve(vec![respond("404 Not Found", ""), ok()]);
+        let spotify = server.client(2);
+        let res = spotify.call(spotify.get("/me"));
+        assert!(matches!(res, Err(Error::NotFound { .. })));
+    }
+
+    #[test]
+    fn test_server_errors_only_retry_idempotent_requests() {
+        let bad_gateway = || respond("502 Bad Gateway", "");
+        let server = serve(vec![bad_gateway(), ok()]);
+        let spotify = server.client(2);
+        assert_eq!(spotify.call(spotify.get("/me")).unwrap().status(), 200);
+
+        // Replacing a playlist's items twice is the same as once
+        let server = serve(vec![bad_gateway(), ok()]);
+        let spotify = server.client(2);
+        let res = spotify.send_json(spotify.put("/playlists/p/tracks"), ureq::json!({"uris": []}));
+        assert_eq!(res.unwrap().status(), 200);
+
+        // Appending or moving twice isn't
+        let append = ureq::json!({"uris": ["spotify:track:1"]});
+        let reorder = ureq::json!({"range_start": 0, "insert_before": 2});
+        for (method, body) in [("POST", append), ("PUT", reorder)] {
+            let server = serve(vec![bad_gateway(), ok()]);
+            let spotify = server.client(2);
+            let res = spotify.send_json(spotify.request(method, "/playlists/p/tracks"), body);
+            assert!(matches!(res, Err(Error::Api { status: 502, .. })), "{}", method);
+            assert_eq!(server.requests().len(), 1);
+        }
+
+        // But a rate limit means nothing happened, so it's retried whatever the request
+        let server = serve(vec![too_many(), ok()]);
+        let spotify = server.client(2);
+        let res = spotify.send_json(spotify.post("/playlists/p/tracks"), ureq::json!({"uris": []}));
+        assert_eq!(res.unwrap().status(), 200);
+    }
+
+    fn expired() -> String {
+        respond("401 Unauthorized", r#"{"error":{"status":401,"message":"The access token expired"}}"#)
+    }
+
+    #[test]
+    fn test_refresh_on_expiry() {
+        let refreshes = Rc::new(std::cell::Cell::new(0));
+        let count = refreshes.clone();
+        let refresh: Refresh = Box::new(move || {
+            count.set(count.get() + 1);
+            Ok(Token::user("fresh".to_owned()))
+        });
+
+        let server = serve(vec![expired(), ok(), ok()]);
+        let spotify = server.client(0).with_refresh(refresh);
+        assert_eq!(spotify.call(spotify.get("/me")).unwrap().status(), 200);
+        // Requests built after the refresh carry the new token too
+        assert_eq!(spotify.call(spotify.get("/me")).unwrap().status(), 200);
+        assert_eq!(refreshes.get(), 1);
+        assert_eq!(tokens(&server), vec!["token", "fresh", "fresh"]);
+
+        // Only once per request, a token that's still rejected is an auth failure
+        let server = serve(vec![expired(), expired()]);
+        let spotify = server.client(0).with_refresh(Box::new(|| Ok(Token::user("fresh".to_owned()))));
+        assert!(matches!(spotify.call(spotify.get("/me")), Err(Error::Auth { .. })));
+
+        // Nothing to refresh with, as with an access token from the environment
+        let server = serve(vec![expired()]);
+        let spotify = server.client(0);
+        assert!(matches!(spotify.call(spotify.get("/me")), Err(Error::Auth { .. })));
+    }
+}
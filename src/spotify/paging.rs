@@ -0,0 +1,161 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::spotify::{Error, SpotifyClient};
+
+/// Most list endpoints won't go higher than this
+const PAGE_SIZE: usize = 50;
+
+/// The bits of Spotify's paging object we need
+#[derive(Deserialize)]
+struct Page<T> {
+    items: Vec<T>,
+    next: Option<String>,
+}
+
+/// Iterates over every item of a list endpoint, following `next` links.
+///
+/// Pages are only fetched once the previous one has been used up, so stopping early
+/// (with `take`, `find` and friends, or `limit`) saves the requests for the rest.
+/// An error ends the iteration after it's been yielded.
+pub struct Paginator<'a, T> {
+    client: &'a SpotifyClient,
+    next: Option<ureq::Request>,
+    first_page: bool,
+    /// Search wraps its paging object, as in `{"tracks": {...}}`
+    key: Option<&'static str>,
+    items: VecDeque<T>,
+    remaining: Option<usize>,
+}
+
+impl<'a, T: DeserializeOwned> Paginator<'a, T> {
+    fn new(client: &'a SpotifyClient, request: ureq::Request) -> Self {
+        Paginator {
+            client,
+            next: Some(request),
+            first_page: true,
+            key: None,
+            items: VecDeque::new(),
+            remaining: None,
+        }
+    }
+
+    /// For responses that keep the paging object under `key`
+    pub fn key(mut self, key: &'static str) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Stops after `limit` items, asking for no more than that in the first place
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.remaining = Some(limit);
+        self
+    }
+
+    fn fetch(&mut self, request: ureq::Request) -> Result<(), Error> {
+        let request = match self.first_page {
+            // Later pages carry the limit in their `next` links
+            true => {
+                let page_size = self.remaining.unwrap_or(PAGE_SIZE).clamp(1, PAGE_SIZE);
+                request.query("limit", &page_size.to_string())
+            }
+            false => request,
+        };
+        self.first_page = false;
+
+        let response = self.client.call(request)?;
+        let page: Page<T> = match self.key {
+            None => response.into_json()?,
+            Some(key) => response
+                .into_json::<HashMap<String, Page<T>>>()?
+                .remove(key)
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Response has no {}", key))
+                })?,
+        };
+
+        self.items.extend(page.items);
+        self.next = page.next.map(|url| self.client.get_url(&url));
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for Paginator<'_, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        while self.items.is_empty() {
+            let request = self.next.take()?;
+            if let Err(e) = self.fetch(request) {
+                return Some(Err(e));
+            }
+        }
+
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        self.items.pop_front().map(Ok)
+    }
+}
+
+impl SpotifyClient {
+    /// Pages through the list `request` asks for, see `Paginator`
+    pub fn paginate<T: DeserializeOwned>(&self, request: ureq::Request) -> Paginator<'_, T> {
+        Paginator::new(self, request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::test_server::{respond, TestServer};
+
+    /// Serves `pages` pages of two numbers each
+    fn serve(pages: usize) -> (SpotifyClient, TestServer) {
+        let server = TestServer::bind();
+        let responses = (0..pages)
+            .map(|page| {
+                let next = match page + 1 < pages {
+                    true => format!("\"{}/list?offset={}&limit=2\"", server.url, (page + 1) * 2),
+                    false => "null".to_owned(),
+                };
+                respond("200 OK", &format!("{{\"items\":[{},{}],\"next\":{}}}", page * 2, page * 2 + 1, next))
+            })
+            .collect();
+        server.answer(responses);
+        (server.client(5), server)
+    }
+
+    fn targets(server: &TestServer) -> Vec<String> {
+        server.requests().into_iter().map(|r| r.target).collect()
+    }
+
+    #[test]
+    fn test_paginator() {
+        let (client, server) = serve(3);
+        let items: Vec<u32> = client.paginate(client.get("/list")).collect::<Result<_, _>>().unwrap();
+        assert_eq!(items, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            targets(&server),
+            vec!["/list?limit=50", "/list?offset=2&limit=2", "/list?offset=4&limit=2"]
+        );
+    }
+
+    #[test]
+    fn test_paginator_stops_early() {
+        let (client, server) = serve(3);
+        let items: Vec<u32> = client.paginate(client.get("/list")).limit(3).collect::<Result<_, _>>().unwrap();
+        assert_eq!(items, vec![0, 1, 2]);
+        assert_eq!(targets(&server), vec!["/list?limit=3", "/list?offset=2&limit=2"]);
+
+        let (client, server) = serve(3);
+        let found = client.paginate::<u32>(client.get("/list")).find(|n| matches!(n, Ok(1)));
+        assert!(found.is_some());
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -1,134 +1,385 @@
-use std::collections::HashMap;
+use std::hash::Hash;
 
 use serde::Deserialize;
 
-use crate::spotify::types::{PlaylistTrackObject, Token, TrackObject, Uri};
-use crate::spotify::user;
-use crate::util::UreqOrJSONError;
+use crate::spotify::reorder;
+use crate::spotify::types::{PlaylistTrackObject, Uri};
+use crate::spotify::{Error, SpotifyClient};
 
-pub fn add_to_playlist(id: &str, uris: &[Uri], token: &Token) -> Result<(), ureq::Error> {
-    for chunk in uris.chunks(100) {
-        add_to_playlist_helper(id, chunk, token)?;
-    }
+/// Most items a playlist can hold
+pub const MAX_PLAYLIST_ITEMS: usize = 10_000;
+
+/// Most items a single add or replace can carry
+const WRITE_CHUNK: usize = 100;
 
-    Ok(())
+#[derive(Deserialize)]
+struct CreatePlaylistRes {
+    id: Uri,
+    href: String,
 }
 
-fn add_to_playlist_helper(id: &str, uris: &[Uri], token: &Token) -> Result<(), ureq::Error> {
-    let mut body: HashMap<String, Vec<String>> = HashMap::new();
-    let uris: Vec<String> = uris.iter().map(|u| &u.0).cloned().collect();
-    body.insert("uris".to_owned(), uris);
+#[derive(Deserialize)]
+struct PlaylistTotal {
+    tracks: Total,
+}
 
-    let _res = ureq::post(&(crate::spotify::BASE_URL.to_owned() + "/playlists/" + id + "/tracks"))
-        .query("position", "0")
-        .set("Authorization", &("Bearer ".to_owned() + &token.0))
-        .send_json(body)?;
+#[derive(Deserialize)]
+struct Total {
+    total: usize,
+}
 
-    Ok(())
+#[derive(Deserialize)]
+struct PlaylistSnapshot {
+    snapshot_id: String,
 }
 
-pub fn set_playlist(id: &str, uris: &[Uri], token: &Token) -> Result<(), ureq::Error> {
-    if uris.is_empty() {
-        set_playlist_helper(id, &Vec::new(), token)?;
-        return Ok(());
+impl SpotifyClient {
+    /// Appends `uris` to the end of the playlist, in order
+    pub fn add_to_playlist(&self, id: &str, uris: &[Uri]) -> Result<(), Error> {
+        self.require_user()?;
+        let before = self.get_playlist_count(id)?;
+        check_size(before + uris.len())?;
+
+        self.write_chunks(id, uris, false)?;
+        self.check_count(id, before + uris.len())
     }
 
-    for chunk in uris.chunks(100) {
-        set_playlist_helper(id, chunk, token)?;
+    /// Replaces everything in the playlist with `uris`, in order
+    pub fn set_playlist(&self, id: &str, uris: &[Uri]) -> Result<(), Error> {
+        self.require_user()?;
+        check_size(uris.len())?;
+        if uris.is_empty() {
+            self.send_json(self.put(&format!("/playlists/{}/tracks", id)), ureq::json!({"uris": []}))?;
+            return Ok(());
+        }
+
+        self.write_chunks(id, uris, true)?;
+        self.check_count(id, uris.len())
     }
 
-    Ok(())
-}
+    /// Moves items around until the playlist, which was `current` at `snapshot`, is in `target`
+    /// order, items being told apart by their keys. Unlike [`set_playlist`](Self::set_playlist)
+    /// this keeps when each item was added, and works with local files in the playlist too.
+    /// Each move is made against the snapshot the one before it returned. Returns how many
+    /// moves it took.
+    pub fn reorder_playlist<K: Hash + Eq>(&self, id: &str, snapshot: &str, current: &[K], target: &[K]) -> Result<usize, Error> {
+        self.require_user()?;
+        let moves = reorder::moves(current, target).ok_or(Error::NotAReordering)?;
+        if moves.is_empty() {
+            return Ok(0);
+        }
+        self.check_snapshot(id, snapshot)?;
+
+        let path = format!("/playlists/{}/tracks", id);
+        let mut snapshot = snapshot.to_owned();
+        for (n, m) in moves.iter().enumerate() {
+            let body = ureq::json!({
+                "range_start": m.range_start,
+                "insert_before": m.insert_before,
+                "range_length": m.range_length,
+                "snapshot_id": snapshot,
+            });
+            snapshot = self
+                .send_json(self.put(&path), body)
+                .and_then(|res| Ok(res.into_json::<PlaylistSnapshot>()?.snapshot_id))
+                .map_err(|e| Error::MoveFailed {
+                    n: n + 1,
+                    moves: moves.len(),
+                    source: Box::new(e),
+                })?;
+        }
 
-// No more than 100 to be passed into this function!
-fn set_playlist_helper(id: &str, uris: &[Uri], token: &Token) -> Result<(), ureq::Error> {
-    let mut body: HashMap<String, Vec<String>> = HashMap::new();
-    let uris: Vec<String> = uris.iter().map(|u| &u.0).cloned().collect();
-    body.insert("uris".to_owned(), uris);
+        Ok(moves.len())
+    }
 
-    let _res = ureq::put(&(crate::spotify::BASE_URL.to_owned() + "/playlists/" + id + "/tracks"))
-        .set("Authorization", &("Bearer ".to_owned() + &token.0))
-        .send_json(&body)?;
+    /// Sends `uris` a chunk at a time. With `replace` the first chunk replaces what's in
+    /// the playlist, everything else is appended after it.
+    fn write_chunks(&self, id: &str, uris: &[Uri], replace: bool) -> Result<(), Error> {
+        let path = format!("/playlists/{}/tracks", id);
+        let chunks = uris.len().div_ceil(WRITE_CHUNK);
 
-    Ok(())
-}
+        for (n, chunk) in uris.chunks(WRITE_CHUNK).enumerate() {
+            let request = match replace && n == 0 {
+                true => self.put(&path),
+                false => self.post(&path),
+            };
+            let uris: Vec<&str> = chunk.iter().map(|u| u.0.as_str()).collect();
+            self.send_json(request, ureq::json!({ "uris": uris }))
+                .map_err(|e| Error::WriteFailed {
+                    chunk: n + 1,
+                    chunks,
+                    first: n * WRITE_CHUNK + 1,
+                    last: n * WRITE_CHUNK + chunk.len(),
+                    source: Box::new(e),
+                })?;
+        }
 
-#[derive(Deserialize)]
-struct GetPlaylistItemsRes {
-    pub items: Vec<PlaylistTrackObject>,
-    pub next: Option<String>,
-}
+        Ok(())
+    }
+
+    /// How many items the playlist has right now
+    pub fn get_playlist_count(&self, id: &str) -> Result<usize, Error> {
+        let request = self.get(&format!("/playlists/{}", id)).query("fields", "tracks.total");
+        Ok(self.call(request)?.into_json::<PlaylistTotal>()?.tracks.total)
+    }
+
+    /// Makes sure a write left the playlist as long as it should be
+    fn check_count(&self, id: &str, expected: usize) -> Result<(), Error> {
+        match self.get_playlist_count(id)? {
+            found if found == expected => Ok(()),
+            found => Err(Error::CountMismatch { expected, found }),
+        }
+    }
 
-pub fn get_playlist_items(id: &str, token: &Token) -> Result<Vec<TrackObject>, UreqOrJSONError> {
-    let mut tracks: Vec<TrackObject> = Vec::new();
-    let mut done = false;
-    let mut offset = 0u16;
+    /// Every item in the playlist, with when and by whom it was added
+    pub fn get_playlist_items(&self, id: &str) -> Result<Vec<PlaylistTrackObject>, Error> {
+        // Without this, episodes come dressed up as tracks
+        let request = self.get(&format!("/playlists/{}/tracks", id)).query("additional_types", "track,episode");
+        self.paginate(request).collect()
+    }
 
-    let agent = ureq::Agent::new();
+    /// The playlist's items along with the snapshot ID they belong to. If the playlist changes
+    /// while the pages are read, that's a conflict just like a change before writing would be.
+    pub fn get_playlist_snapshot_items(&self, id: &str) -> Result<(String, Vec<PlaylistTrackObject>), Error> {
+        let snapshot = self.get_playlist_snapshot(id)?;
+        let items = self.get_playlist_items(id)?;
+        self.check_snapshot(id, &snapshot)?;
+        Ok((snapshot, items))
+    }
 
-    while !done {
-        match get_playlist_items_helper(id, token, &agent, offset) {
-            Ok((playlist_tracks, is_done)) => {
-                match is_done {
-                    false => offset += 50,
-                    true => done = true,
-                }
+    /// Identifies the playlist's current version, any change to it gets a new one
+    pub fn get_playlist_snapshot(&self, id: &str) -> Result<String, Error> {
+        let request = self.get(&format!("/playlists/{}", id)).query("fields", "snapshot_id");
+        Ok(self.call(request)?.into_json::<PlaylistSnapshot>()?.snapshot_id)
+    }
 
-                tracks.extend(playlist_tracks.into_iter().map(|p| p.track));
-            }
-            Err(e) => return Err(e),
+    /// Fails with [`Error::PlaylistChanged`] if the playlist isn't at `snapshot` anymore
+    pub fn check_snapshot(&self, id: &str, snapshot: &str) -> Result<(), Error> {
+        match self.get_playlist_snapshot(id)? {
+            current if current == snapshot => Ok(()),
+            _ => Err(Error::PlaylistChanged),
         }
     }
 
-    Ok(tracks)
-}
+    pub fn create_playlist(&self, name: &str) -> Result<(Uri, String), Error> {
+        let id = self.get_user_id()?;
 
-pub fn get_playlist_uris(id: &str, token: &Token) -> Result<Vec<Uri>, UreqOrJSONError> {
-    Ok(get_playlist_items(id, token)?
-        .into_iter()
-        .map(|t| t.uri)
-        .collect())
+        let res = self
+            .send_json(self.post(&format!("/users/{}/playlists", id.0)), ureq::json!({"name": name}))?
+            .into_json::<CreatePlaylistRes>()?;
+
+        Ok((res.id, res.href))
+    }
 }
 
-fn get_playlist_items_helper(
-    id: &str,
-    token: &Token,
-    agent: &ureq::Agent,
-    offset: u16,
-) -> Result<(Vec<PlaylistTrackObject>, bool), UreqOrJSONError> {
-    // Returns Ok(vec![list of uris], true) if there is no next page
-    // Returns Ok(vec![list of uris], false) if we need to keep fetching
-
-    let res: GetPlaylistItemsRes = agent
-        .get(&(crate::spotify::BASE_URL.to_owned() + "/playlists/" + id + "/tracks"))
-        .set("Authorization", &("Bearer ".to_owned() + &token.0))
-        .query_pairs(vec![
-            ("offset", offset.to_string().as_str()),
-            ("limit", "50"),
-        ])
-        .call()?
-        .into_json()?;
-
-    match res.next.as_deref() {
-        None => Ok((res.items, true)),
-        Some("null") => Ok((res.items, true)),
-        Some(..) => Ok((res.items, false)),
+fn check_size(count: usize) -> Result<(), Error> {
+    match count {
+        count if count > MAX_PLAYLIST_ITEMS => Err(Error::TooManyItems { count }),
+        _ => Ok(()),
     }
 }
 
-#[derive(Deserialize)]
-struct CreatePlaylistRes {
-    id: Uri,
-    href: String,
+/// Pulls the ID out of a bare playlist ID, a spotify:playlist: URI or an open.spotify.com link
+pub fn parse_playlist_id(input: &str) -> Option<&str> {
+    let input = input.trim();
+    let id = if input.starts_with("spotify:") {
+        // Older URIs look like spotify:user:<user>:playlist:<id>
+        let mut parts = input.split(':');
+        parts.find(|part| *part == "playlist")?;
+        parts.next()?
+    } else if let Some(rest) = input.strip_prefix("https://").or(input.strip_prefix("http://")) {
+        let path = rest.strip_prefix("open.spotify.com/")?;
+        let path = path.split(['?', '#']).next()?;
+        // Links may have a locale in front, like /intl-de/playlist/<id>
+        let mut segments = path.split('/');
+        segments.find(|segment| *segment == "playlist")?;
+        segments.next()?
+    } else {
+        input
+    };
+
+    // Spotify IDs are 22 characters of base62
+    (id.len() == 22 && id.bytes().all(|b| b.is_ascii_alphanumeric())).then_some(id)
 }
 
-pub fn create_playlist(name: &str, token: &Token) -> Result<(Uri, String), UreqOrJSONError> {
-    let id = user::get_user_id(token)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::test_server::{self, respond, TestServer};
+    use crate::spotify::types::PlaylistItem;
+
+    fn serve(responses: Vec<String>) -> (SpotifyClient, TestServer) {
+        let server = test_server::serve(responses);
+        (server.client(0), server)
+    }
+
+    /// Each request as "METHOD target" plus the number of URIs in its body,
+    /// or the body itself if it has none
+    fn log(server: &TestServer) -> Vec<String> {
+        server
+            .requests()
+            .into_iter()
+            .map(|r| {
+                let request = format!("{} {}", r.method, r.target);
+                match (r.body.is_empty(), r.body.matches("spotify:track:").count()) {
+                    (true, _) => request,
+                    (false, 0) => format!("{} {}", request, r.body),
+                    (false, uris) => format!("{} {}", request, uris),
+                }
+            })
+            .collect()
+    }
+
+    fn total(n: usize) -> String {
+        respond("200 OK", &format!("{{\"tracks\":{{\"total\":{}}}}}", n))
+    }
+
+    fn uris(n: usize) -> Vec<Uri> {
+        (0..n).map(|i| Uri(format!("spotify:track:{}", i))).collect()
+    }
 
-    let res = ureq::post(&(crate::spotify::BASE_URL.to_owned() + "/users/" + id.0.as_str() + "/playlists"))
-        .set("Authorization", &("Bearer ".to_owned() + &token.0))
-        .send_json(ureq::json!({"name": name}))?
-        .into_json::<CreatePlaylistRes>()?;
+    #[test]
+    fn test_set_playlist_in_order() {
+        let snapshot = respond("201 Created", r#"{"snapshot_id":"s"}"#);
+        let (client, requests) = serve(vec![snapshot.clone(), snapshot.clone(), snapshot.clone(), total(250)]);
+        client.set_playlist("p", &uris(250)).unwrap();
+        assert_eq!(
+            log(&requests),
+            vec![
+                "PUT /playlists/p/tracks 100",
+                "POST /playlists/p/tracks 100",
+                "POST /playlists/p/tracks 50",
+                "GET /playlists/p?fields=tracks.total",
+            ]
+        );
+
+        // Appending goes to the end, no position
+        let (client, requests) = serve(vec![total(10), snapshot.clone(), snapshot.clone(), total(130)]);
+        client.add_to_playlist("p", &uris(120)).unwrap();
+        assert_eq!(log(&requests)[1], "POST /playlists/p/tracks 100");
+        assert_eq!(log(&requests)[2], "POST /playlists/p/tracks 20");
+
+        let (client, _) = serve(vec![snapshot.clone(), total(99)]);
+        let res = client.set_playlist("p", &uris(100));
+        assert!(matches!(res, Err(Error::CountMismatch { expected: 100, found: 99 })));
+
+        let res = client.set_playlist("p", &uris(MAX_PLAYLIST_ITEMS + 1));
+        assert!(matches!(res, Err(Error::TooManyItems { .. })));
+    }
 
-    Ok((res.id, res.href))
+    #[test]
+    fn test_snapshot_conflicts() {
+        let snapshot = |id: &str| respond("200 OK", &format!("{{\"snapshot_id\":\"{}\"}}", id));
+        let empty = respond("200 OK", r#"{"items":[],"next":null}"#);
+
+        let (client, requests) = serve(vec![snapshot("a"), empty.clone(), snapshot("a"), snapshot("a")]);
+        let (read, items) = client.get_playlist_snapshot_items("p").unwrap();
+        assert_eq!((read.as_str(), items.len()), ("a", 0));
+        client.check_snapshot("p", &read).unwrap();
+        assert_eq!(log(&requests)[0], "GET /playlists/p?fields=snapshot_id");
+
+        // Changed while the pages were read
+        let (client, _) = serve(vec![snapshot("a"), empty, snapshot("b")]);
+        let res = client.get_playlist_snapshot_items("p");
+        assert!(matches!(res, Err(Error::PlaylistChanged)));
+
+        let (client, _) = serve(vec![snapshot("b")]);
+        let res = client.check_snapshot("p", "a");
+        assert_eq!(res.unwrap_err().exit_code(), crate::spotify::error::EXIT_CONFLICT);
+    }
+
+    #[test]
+    fn test_playlist_items() {
+        let track = r#"{"href":"h","id":"1","name":"One","uri":"spotify:track:1","track_number":1}"#;
+        let page = format!(
+            r#"{{"items":[{{"added_at":"2024-01-01T00:00:00Z","added_by":{{"id":"someone","type":"user"}},"is_local":false,"track":{}}},{{"added_at":null,"added_by":null,"is_local":false,"track":{}}}],"next":null}}"#,
+            track, track
+        );
+        let (client, _) = serve(vec![respond("200 OK", &page)]);
+        let items = client.get_playlist_items("p").unwrap();
+        assert_eq!(items[0].added_at.as_deref(), Some("2024-01-01T00:00:00Z"));
+        assert_eq!(items[0].added_by.as_ref().unwrap().id.0, "someone");
+        // Added before Spotify kept track
+        assert!(items[1].added_at.is_none() && items[1].added_by.is_none());
+
+        let item = |is_local: bool, track: &str| format!(r#"{{"added_at":null,"added_by":null,"is_local":{},"track":{}}}"#, is_local, track);
+        let items = [
+            item(false, track),
+            item(false, r#"{"type":"episode","href":"h","id":"2","name":"Two","uri":"spotify:episode:2","release_date":"2024-02-03"}"#),
+            item(true, r#"{"type":"track","href":null,"id":null,"name":"Three","uri":"spotify:local:A:B:Three:180","track_number":0}"#),
+            item(false, "null"),
+            item(false, r#"{"type":"track","id":null,"uri":"spotify:track:4"}"#),
+        ];
+        let page = format!(r#"{{"items":[{}],"next":null}}"#, items.join(","));
+        let (client, requests) = serve(vec![respond("200 OK", &page)]);
+        let items = client.get_playlist_items("p").unwrap();
+        assert!(log(&requests)[0].contains("additional_types=track%2Cepisode"));
+        assert!(matches!(&items[0].track, PlaylistItem::Track(track) if track.name == "One"));
+        assert!(matches!(&items[1].track, PlaylistItem::Episode(episode) if episode.release_date.as_deref() == Some("2024-02-03")));
+        assert!(matches!(&items[2].track, PlaylistItem::Local(local) if local.uri.0 == "spotify:local:A:B:Three:180"));
+        assert!(matches!(&items[3].track, PlaylistItem::Unavailable { uri: None }));
+        assert!(matches!(&items[4].track, PlaylistItem::Unavailable { uri: Some(uri) } if uri.0 == "spotify:track:4"));
+        assert_eq!(items.iter().filter(|item| item.track.is_catalog()).count(), 2);
+
+        // A track that doesn't look like one is an error, not another unavailable item
+        let page = format!(r#"{{"items":[{}],"next":null}}"#, item(false, r#"{"type":"track","href":"h","id":"5","name":"Five","uri":"spotify:track:5"}"#));
+        let (client, _) = serve(vec![respond("200 OK", &page)]);
+        let res = client.get_playlist_items("p");
+        assert!(matches!(&res, Err(Error::Decode(e)) if e.to_string().contains("track_number")), "{:?}", res.err());
+    }
+
+    #[test]
+    fn test_reorder_playlist() {
+        let snapshot = |id: &str| respond("200 OK", &format!("{{\"snapshot_id\":\"{}\"}}", id));
+        let current = ["x", "y", "z", "x", "y", "z"];
+        let target: Vec<&str> = current.iter().rev().cloned().collect();
+        let (client, _) = serve(vec![]);
+        assert_eq!(client.reorder_playlist("p", "a", &current, &current).unwrap(), 0);
+        assert!(matches!(client.reorder_playlist("p", "a", &current, &current[1..]), Err(Error::NotAReordering)));
+
+        // Each move is made against the snapshot the one before returned
+        let moves = reorder::moves(&current, &target).unwrap();
+        let responses = std::iter::once(snapshot("a")).chain((1..=moves.len()).map(|n| snapshot(&n.to_string())));
+        let (client, requests) = serve(responses.collect());
+        assert_eq!(client.reorder_playlist("p", "a", &current, &target).unwrap(), moves.len());
+        let requests = log(&requests);
+        assert_eq!(requests.len(), moves.len() + 1);
+        assert!(requests[1].starts_with("PUT /playlists/p/tracks {"));
+        assert!(requests[1].contains(r#""snapshot_id":"a""#));
+        assert!(requests[2].contains(r#""snapshot_id":"1""#));
+
+        let failed = respond("502 Bad Gateway", "");
+        let (client, _) = serve(vec![snapshot("a"), snapshot("1"), failed]);
+        let res = client.reorder_playlist("p", "a", &current, &target);
+        assert!(matches!(res, Err(Error::MoveFailed { n: 2, .. })));
+    }
+
+    #[test]
+    fn test_set_playlist_reports_failed_chunk() {
+        let snapshot = respond("201 Created", r#"{"snapshot_id":"s"}"#);
+        let failed = respond("403 Forbidden", r#"{"error":{"status":403,"message":"Nope"}}"#);
+        let (client, requests) = serve(vec![snapshot.clone(), snapshot, failed]);
+        let res = client.set_playlist("p", &uris(250));
+        assert!(matches!(
+            &res,
+            Err(Error::WriteFailed { chunk: 3, chunks: 3, first: 201, last: 250, source })
+                if matches!(**source, Error::Permission { .. })
+        ));
+        assert_eq!(res.unwrap_err().exit_code(), crate::spotify::error::EXIT_PERMISSION);
+        assert_eq!(log(&requests).len(), 3);
+    }
+
+    #[test]
+    fn test_parse_playlist_id() {
+        let id = "37i9dQZF1DXcBWIGoYBM5M";
+        assert_eq!(parse_playlist_id(id), Some(id));
+        assert_eq!(parse_playlist_id(&format!("spotify:playlist:{}", id)), Some(id));
+        assert_eq!(parse_playlist_id(&format!("spotify:user:someone:playlist:{}", id)), Some(id));
+        assert_eq!(parse_playlist_id(&format!("https://open.spotify.com/playlist/{}?si=abc", id)), Some(id));
+        assert_eq!(parse_playlist_id(&format!("https://open.spotify.com/intl-de/playlist/{}", id)), Some(id));
+
+        assert_eq!(parse_playlist_id("taylor"), None);
+        assert_eq!(parse_playlist_id(&format!("spotify:track:{}", id)), None);
+        assert_eq!(parse_playlist_id(&format!("https://example.com/playlist/{}", id)), None);
+    }
 }
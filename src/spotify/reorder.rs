@@ -0,0 +1,161 @@
+// Works out how to get a playlist from one order to another by moving items around. Unlike
+// rewriting the playlist, moves keep when each item was added.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// One reorder request: `range_length` items starting at `range_start` go just before the
+/// item at `insert_before`, both positions counted before the move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub range_start: usize,
+    pub insert_before: usize,
+    pub range_length: usize,
+}
+
+/// The moves that turn `current` into `target`, or None if `target` isn't a reordering of it.
+/// Items are told apart by their keys, like URIs or where they were when the playlist was read.
+///
+/// The longest run of items that are already in target order stays put and every other item
+/// moves once, which is as few items as possible. Items that end up next to each other and
+/// already are move together.
+pub fn moves<K: Hash + Eq>(current: &[K], target: &[K]) -> Option<Vec<Move>> {
+    // Where each item of the playlist should end up, as it's moved around
+    let mut order = target_positions(current, target)?;
+    let keep = longest_increasing(&order);
+
+    let mut moves = Vec::new();
+    let mut next = 0;
+    while next < order.len() {
+        if keep[next] {
+            next += 1;
+            continue;
+        }
+
+        let from = order.iter().position(|&t| t == next)?;
+        let mut length = 1;
+        while next + length < order.len() && !keep[next + length] && order.get(from + length) == Some(&(next + length)) {
+            length += 1;
+        }
+
+        // Everything before `next` is in order by now, so it goes right after its predecessor
+        let insert_before = match next {
+            0 => 0,
+            _ => order.iter().position(|&t| t == next - 1)? + 1,
+        };
+        if insert_before != from {
+            moves.push(Move {
+                range_start: from,
+                insert_before,
+                range_length: length,
+            });
+            apply(&mut order, moves[moves.len() - 1]);
+        }
+        next += length;
+    }
+
+    Some(moves)
+}
+
+/// Does to `items` what Spotify does to a playlist for `m`
+fn apply<T>(items: &mut Vec<T>, m: Move) {
+    let moved: Vec<T> = items.drain(m.range_start..m.range_start + m.range_length).collect();
+    let at = match m.insert_before > m.range_start {
+        true => m.insert_before - m.range_length,
+        false => m.insert_before,
+    };
+    items.splice(at..at, moved);
+}
+
+/// For each item of `current`, its position in `target`. Repeated keys are matched up in order.
+fn target_positions<K: Hash + Eq>(current: &[K], target: &[K]) -> Option<Vec<usize>> {
+    if current.len() != target.len() {
+        return None;
+    }
+    let mut positions: HashMap<&K, VecDeque<usize>> = HashMap::new();
+    for (i, key) in target.iter().enumerate() {
+        positions.entry(key).or_default().push_back(i);
+    }
+    current
+        .iter()
+        .map(|key| positions.get_mut(key)?.pop_front())
+        .collect()
+}
+
+/// Which values of `order` (a permutation of 0..len) are on a longest increasing subsequence,
+/// indexed by value
+fn longest_increasing(order: &[usize]) -> Vec<bool> {
+    // tails[k] is where the increasing run of length k + 1 with the smallest last value ends
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![None; order.len()];
+    for (i, &value) in order.iter().enumerate() {
+        let k = tails.partition_point(|&j| order[j] < value);
+        if k > 0 {
+            prev[i] = Some(tails[k - 1]);
+        }
+        match k == tails.len() {
+            true => tails.push(i),
+            false => tails[k] = i,
+        }
+    }
+
+    let mut keep = vec![false; order.len()];
+    let mut at = tails.last().copied();
+    while let Some(i) = at {
+        keep[order[i]] = true;
+        at = prev[i];
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that the moves get from `current` to `target`, returning how many there were
+    fn check(current: &str, target: &str) -> usize {
+        let (mut playlist, wanted): (Vec<char>, Vec<char>) = (current.chars().collect(), target.chars().collect());
+        let moves = moves(&playlist, &wanted).unwrap();
+        for m in &moves {
+            assert!(m.range_start + m.range_length <= playlist.len() && m.insert_before <= playlist.len());
+            apply(&mut playlist, *m);
+        }
+        assert_eq!(playlist, wanted, "{} to {}", current, target);
+        moves.len()
+    }
+
+    #[test]
+    fn test_moves() {
+        assert_eq!(check("", ""), 0);
+        assert_eq!(check("abcdef", "abcdef"), 0);
+        assert_eq!(check("abcdef", "bcdefa"), 1);
+        assert_eq!(check("abcdef", "fabcde"), 1);
+        assert_eq!(check("abcdef", "fedcba"), 5);
+        // A block that stays together moves in one go
+        assert_eq!(check("defabc", "abcdef"), 1);
+        assert_eq!(check("axbcyd", "abcdxy"), 2);
+        assert_eq!(check("abab", "aabb"), 1);
+        check("cadbfe", "abcdef");
+        check("baabcb", "aabbbc");
+        check("hgfedcbaxyz", "xazbycgdhef");
+
+        // Every order of six items, none of which should take more than five moves
+        fn permutations(rest: String, done: String, out: &mut Vec<String>) {
+            if rest.is_empty() {
+                out.push(done.clone());
+            }
+            for (i, c) in rest.char_indices() {
+                permutations(format!("{}{}", &rest[..i], &rest[i + 1..]), format!("{}{}", done, c), out);
+            }
+        }
+        let mut all = Vec::new();
+        permutations("abcdef".to_owned(), String::new(), &mut all);
+        assert_eq!(all.len(), 720);
+        for order in &all {
+            assert!(check(order, "abcdef") <= 5);
+        }
+
+        assert!(moves(&['a', 'b', 'c'], &['a', 'b', 'd']).is_none());
+        assert!(moves(&['a', 'b', 'c'], &['a', 'b']).is_none());
+    }
+}
@@ -0,0 +1,11 @@
+use crate::spotify::types::TrackObject;
+use crate::spotify::{Error, SpotifyClient};
+
+impl SpotifyClient {
+    /// Tracks matching `query`, which can use Spotify's filters like `artist:` and `year:`
+    #[allow(dead_code)] // No command searches yet
+    pub fn search_tracks(&self, query: &str, num: usize) -> Result<Vec<TrackObject>, Error> {
+        let request = self.get("/search").query("q", query).query("type", "track");
+        self.paginate(request).key("tracks").limit(num).collect()
+    }
+}
@@ -0,0 +1,74 @@
+// A stand-in for the Web API in tests: answers each connection with the next canned response
+// and keeps what it was asked, so tests can check both sides.
+
+use std::io::{BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+use crate::http;
+use crate::spotify::types::Token;
+use crate::spotify::SpotifyClient;
+use crate::util;
+
+pub use crate::http::Request;
+
+pub struct TestServer {
+    pub url: String,
+    listener: TcpListener,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+/// Starts answering with `responses` right away, for when they don't need the URL
+pub fn serve(responses: Vec<String>) -> TestServer {
+    let server = TestServer::bind();
+    server.answer(responses);
+    server
+}
+
+pub fn respond(status: &str, body: &str) -> String {
+    http::response(status, &[], body)
+}
+
+pub fn respond_with(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    http::response(status, headers, body)
+}
+
+impl TestServer {
+    pub fn bind() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        TestServer {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            listener,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Answers one connection per response, in order, then stops listening
+    pub fn answer(&self, responses: Vec<String>) {
+        let listener = self.listener.try_clone().unwrap();
+        let log = self.requests.clone();
+        std::thread::spawn(move || {
+            for (stream, response) in listener.incoming().zip(responses) {
+                let mut stream = stream.unwrap();
+                let request = Request::read(&mut BufReader::new(&stream)).unwrap();
+                log.lock().unwrap().push(request);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+    }
+
+    /// Everything answered so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// A client for this server, with a user token
+    pub fn client(&self, max_retries: u32) -> SpotifyClient {
+        let config = util::Config {
+            api_url: Some(self.url.clone()),
+            max_retries: Some(max_retries),
+            ..Default::default()
+        };
+        SpotifyClient::new(Token::user("token".to_owned()), &config)
+    }
+}
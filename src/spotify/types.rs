@@ -2,12 +2,97 @@
 // Stolen from cool-spotify-blend, lovingly modified
 
 use serde::{Deserialize, Serialize};
+use ureq::serde_json::Value;
 
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(try_from = "RawPlaylistTrackObject")]
 #[non_exhaustive]
 pub struct PlaylistTrackObject {
-    pub track: TrackObject
+    /// Like 2024-01-01T00:00:00Z, missing for items added before Spotify kept track
+    pub added_at: Option<String>,
+    /// Missing for items added before Spotify kept track
+    pub added_by: Option<PublicUserObject>,
+    pub is_local: bool,
+    pub track: PlaylistItem,
+}
+
+/// What Spotify sends, before we work out what kind of item `track` is
+#[derive(Deserialize)]
+struct RawPlaylistTrackObject {
+    added_at: Option<String>,
+    added_by: Option<PublicUserObject>,
+    #[serde(default)]
+    is_local: bool,
+    track: Option<Value>,
+}
+
+impl TryFrom<RawPlaylistTrackObject> for PlaylistTrackObject {
+    type Error = ureq::serde_json::Error;
+
+    fn try_from(raw: RawPlaylistTrackObject) -> Result<Self, Self::Error> {
+        Ok(PlaylistTrackObject {
+            added_at: raw.added_at,
+            added_by: raw.added_by,
+            is_local: raw.is_local,
+            track: match raw.track {
+                Some(track) => PlaylistItem::from_value(&track, raw.is_local)?,
+                None => PlaylistItem::Unavailable { uri: None },
+            },
+        })
+    }
+}
+
+/// Whatever a playlist holds. Spotify calls all of it a track, but only some of it is one.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlaylistItem {
+    Track(TrackObject),
+    Episode(EpisodeObject),
+    /// A file from someone's computer, which has a URI but nothing else in the catalog
+    Local(LocalTrackObject),
+    /// Removed from Spotify: there's no track at all, or one without an ID
+    Unavailable { uri: Option<Uri> },
+}
+
+impl PlaylistItem {
+    /// Only an item that's really gone is unavailable. Anything else that doesn't decode is an
+    /// error, so a change on Spotify's end can't quietly make every track look removed.
+    fn from_value(track: &Value, is_local: bool) -> Result<Self, ureq::serde_json::Error> {
+        let uri = track.get("uri").and_then(Value::as_str);
+        if is_local || uri.is_some_and(|uri| uri.starts_with("spotify:local:")) {
+            LocalTrackObject::deserialize(track).map(PlaylistItem::Local)
+        } else if track.is_null() || track.get("id").is_some_and(Value::is_null) {
+            Ok(PlaylistItem::Unavailable {
+                uri: uri.map(|uri| Uri(uri.to_owned())),
+            })
+        } else if track.get("type").and_then(Value::as_str) == Some("episode") {
+            EpisodeObject::deserialize(track).map(PlaylistItem::Episode)
+        } else {
+            TrackObject::deserialize(track).map(PlaylistItem::Track)
+        }
+    }
+
+    pub fn uri(&self) -> Option<&Uri> {
+        match self {
+            PlaylistItem::Track(track) => Some(&track.uri),
+            PlaylistItem::Episode(episode) => Some(&episode.uri),
+            PlaylistItem::Local(local) => Some(&local.uri),
+            PlaylistItem::Unavailable { uri } => uri.as_ref(),
+        }
+    }
+
+    /// Tracks and episodes can be written to a playlist, local files and unavailable items can't
+    pub fn is_catalog(&self) -> bool {
+        matches!(self, PlaylistItem::Track(_) | PlaylistItem::Episode(_))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[non_exhaustive]
+pub struct SavedTrackObject {
+    pub added_at: String,
+    pub track: TrackObject,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -22,6 +107,33 @@ pub struct TrackObject {
     pub track_number: u16,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[non_exhaustive]
+pub struct EpisodeObject {
+    pub href: String,
+    pub id: String,
+    pub name: String,
+    pub uri: Uri,
+    pub release_date: Option<String>,
+    pub show: Option<SimplifiedShowObject>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[non_exhaustive]
+pub struct SimplifiedShowObject {
+    pub id: String,
+    pub name: String,
+}
+
+/// Local files come with null IDs and links, all that's left is the name and the
+/// spotify:local: URI (which has the artist, album and title in it)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[non_exhaustive]
+pub struct LocalTrackObject {
+    pub name: Option<String>,
+    pub uri: Uri,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[non_exhaustive]
 pub struct AlbumObject {
@@ -44,6 +156,14 @@ pub struct ArtistObject {
     pub name: String,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[non_exhaustive]
+pub struct SimplifiedPlaylistObject {
+    pub id: String,
+    pub name: String,
+    pub uri: Uri,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Uri(pub String);
 
@@ -63,9 +183,6 @@ impl Uri {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-pub struct SpotifyId(pub String);
-
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[non_exhaustive]
 pub struct UserProfile {
@@ -74,14 +191,39 @@ pub struct UserProfile {
     pub images: Option<Vec<ImageObject>>,
 }
 
+/// Someone else's profile, as much as Spotify tells us in places like `added_by`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[non_exhaustive]
+pub struct PublicUserObject {
+    pub id: UserId,
+    pub display_name: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[non_exhaustive]
 pub struct ImageObject {
     pub url: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct Token(pub String);
+#[derive(Debug, Clone)]
+pub struct Token(pub String, pub TokenKind);
+
+/// App-only tokens come from the client credentials grant and can't touch anything under /me
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    User,
+    App,
+}
+
+impl Token {
+    pub fn user(access_token: String) -> Self {
+        Token(access_token, TokenKind::User)
+    }
+
+    pub fn app(access_token: String) -> Self {
+        Token(access_token, TokenKind::App)
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct UserId(pub String);
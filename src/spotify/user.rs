@@ -1,11 +1,5 @@
-use serde::Deserialize;
-
-use crate::spotify::types::{Token, TrackObject, UserId, UserProfile};
-use crate::util::UreqOrJSONError;
-
-pub fn get_user_id(token: &Token) -> Result<UserId, UreqOrJSONError> {
-    Ok(get_user_profile(token)?.id)
-}
+use crate::spotify::types::{SavedTrackObject, SimplifiedPlaylistObject, TrackObject, UserId, UserProfile};
+use crate::spotify::{Error, SpotifyClient};
 
 pub enum TopTerm {
     Short,
@@ -23,40 +17,37 @@ impl std::fmt::Display for TopTerm {
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct GetUserTopTracksRes {
-    items: Vec<TrackObject>,
-    total: u16,
-}
-
-pub fn get_user_top_tracks(
-    token: &Token,
-    term: &TopTerm,
-    num: u16,
-) -> Result<Vec<TrackObject>, UreqOrJSONError> {
-    let mut tracks: Vec<TrackObject> = Vec::with_capacity(num as usize);
+impl SpotifyClient {
+    pub fn get_user_id(&self) -> Result<UserId, Error> {
+        Ok(self.get_user_profile()?.id)
+    }
 
-    while tracks.len() < num as usize {
-        let res = ureq::get(&(crate::spotify::BASE_URL.to_owned() + "/me/top/tracks"))
-            .set("Authorization", &("Bearer ".to_owned() + &token.0))
-            .query("limit", "50")
-            .query("offset", &tracks.len().to_string())
-            .query("time_range", &term.to_string())
-            .call()?
-            .into_json::<GetUserTopTracksRes>()?;
+    pub fn get_user_top_tracks(&self, term: &TopTerm, num: u16) -> Result<Vec<TrackObject>, Error> {
+        self.require_user()?;
+        let request = self.get("/me/top/tracks").query("time_range", &term.to_string());
+        self.paginate(request).limit(num as usize).collect()
+    }
 
-        tracks.extend(res.items);
-        if tracks.len() >= res.total as usize {
-            break
+    /// The user's liked songs, most recently added first. Needs the user-library-read scope,
+    /// which auth doesn't ask for by default.
+    #[allow(dead_code)] // No command reads them yet
+    pub fn get_saved_tracks(&self, num: Option<usize>) -> Result<Vec<TrackObject>, Error> {
+        self.require_user()?;
+        let tracks = self.paginate::<SavedTrackObject>(self.get("/me/tracks"));
+        match num {
+            Some(num) => tracks.limit(num).map(|saved| saved.map(|s| s.track)).collect(),
+            None => tracks.map(|saved| saved.map(|s| s.track)).collect(),
         }
     }
 
-    Ok(tracks.into_iter().take(num as usize).collect())
-}
+    pub fn get_user_profile(&self) -> Result<UserProfile, Error> {
+        self.require_user()?;
+        Ok(self.call(self.get("/me"))?.into_json::<UserProfile>()?)
+    }
 
-fn get_user_profile(token: &Token) -> Result<UserProfile, UreqOrJSONError> {
-    Ok(ureq::get(&(crate::spotify::BASE_URL.to_owned() + "/me"))
-        .set("Authorization", &("Bearer ".to_owned() + &token.0))
-        .call()?
-        .into_json::<UserProfile>()?)
+    /// Playlists the user owns or follows
+    pub fn get_user_playlists(&self) -> Result<Vec<SimplifiedPlaylistObject>, Error> {
+        self.require_user()?;
+        self.paginate(self.get("/me/playlists")).collect()
+    }
 }
@@ -1,58 +1,760 @@
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::spotify::types::Token;
+use crate::spotify::{Refresh, SpotifyClient};
+
+/// Problems with the config file, always saying which file and where
 #[derive(Error, Debug)]
-pub enum UreqOrJSONError {
-    #[error("Request failed or got 4xx code")]
-    Request(#[from] ureq::Error),
-    #[error("Failure to parse JSON")]
-    Json(#[from] std::io::Error),
+pub enum ConfigError {
+    #[error("{}: failed to read: {source}", .path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}: failed to write: {source}", .path.display())]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}:{line}:{column}: {message}", .path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("{}: {message}", .path.display())]
+    Encryption { path: PathBuf, message: String },
+    #[error("{}: {problem}", location(.path, *.line))]
+    Invalid {
+        path: PathBuf,
+        line: Option<usize>,
+        problem: String,
+    },
+}
+
+fn location(path: &Path, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{}:{}", path.display(), line),
+        None => path.display().to_string(),
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ConfigError {
+    /// EX_CONFIG from sysexits.h
+    pub const EXIT_CODE: i32 = 78;
+
+    /// Something wrong with the selected profile that we can't pin to a line
+    pub fn invalid(path: &ConfigPath, problem: impl Into<String>) -> Self {
+        ConfigError::Invalid {
+            path: config_file_path(path),
+            line: None,
+            problem: problem.into(),
+        }
+    }
+
+    pub fn exit(&self) -> ! {
+        eprintln!("{}", self);
+        std::process::exit(Self::EXIT_CODE)
+    }
+
+    fn parse(path: PathBuf, data: &str, e: toml::de::Error) -> Self {
+        let offset = e.span().map(|span| span.start).unwrap_or(0).min(data.len());
+        let before = &data[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+        ConfigError::Parse {
+            path,
+            line,
+            column,
+            message: e.message().to_owned(),
+        }
+    }
+}
+
+/// Where the config and its token caches are, and which profile we're using
+#[derive(Clone, Debug)]
+pub struct ConfigPath {
+    pub file: PathBuf,
+    /// Token cache of the default profile, see `token_cache_path`
+    pub token_cache: PathBuf,
+    /// Falls back to `default_profile` in the file, then to "default"
+    pub profile: Option<String>,
+}
+
+/// Name of the profile kept in the top level of the file, where configs from
+/// before profiles existed keep working
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Everything in the config file, one `Config` per profile
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    /// Present when secrets in the file are encrypted with a passphrase
+    pub encryption_salt: Option<String>,
+    #[serde(flatten)]
+    pub top_level: Config,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Config>,
+}
+
+impl ConfigFile {
+    /// All profiles with their names, the top level one first if it has anything in it
+    pub fn profiles(&self) -> Vec<(&str, &Config)> {
+        let mut out: Vec<(&str, &Config)> = Vec::new();
+        if !self.profiles.contains_key(DEFAULT_PROFILE) && self.top_level.client_id.is_some() {
+            out.push((DEFAULT_PROFILE, &self.top_level));
+        }
+        out.extend(self.profiles.iter().map(|(name, config)| (name.as_str(), config)));
+        out
+    }
+
+    pub fn profile_name<'a>(&'a self, path: &'a ConfigPath) -> &'a str {
+        path.profile
+            .as_deref()
+            .or(self.default_profile.as_deref())
+            .unwrap_or(DEFAULT_PROFILE)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Config> {
+        match self.profiles.get(name) {
+            Some(config) => Some(config),
+            None if name == DEFAULT_PROFILE => Some(&self.top_level),
+            None => None,
+        }
+    }
+
+    /// The client secret and refresh token of every profile
+    fn secrets_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        std::iter::once(&mut self.top_level)
+            .chain(self.profiles.values_mut())
+            .flat_map(|c| [c.client_secret.as_mut(), c.refresh_token.as_mut()])
+            .flatten()
+    }
+
+    pub fn set(&mut self, name: &str, config: Config) {
+        if name == DEFAULT_PROFILE && !self.profiles.contains_key(name) {
+            self.top_level = config;
+        } else {
+            self.profiles.insert(name.to_owned(), config);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Config {
     pub refresh_token: Option<String>,
     pub client_id: Option<String>,
     pub client_secret: Option<String>,
+    /// Must match one of the redirect URIs registered for the app
+    pub redirect_uri: Option<String>,
+    /// Where the auth listener binds, derived from the redirect URI if unset
+    pub bind_address: Option<String>,
+    /// Requested on top of the default scopes
+    pub extra_scopes: Option<Vec<String>>,
+    /// What Spotify actually granted at the last auth
+    pub granted_scopes: Option<Vec<String>>,
+    /// Sends API requests somewhere other than api.spotify.com, like a mock server
+    pub api_url: Option<String>,
+    /// Same for accounts.spotify.com, where tokens come from
+    pub accounts_url: Option<String>,
+    /// How often a rate limited or failed request is retried before giving up
+    pub max_retries: Option<u32>,
+    /// Short names for playlist IDs, accepted anywhere a playlist is
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// Where a resolved value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Env,
+    File,
+    Unset,
+}
+
+pub const ENV_CLIENT_ID: &str = "SPOTIFY_CLI_CLIENT_ID";
+pub const ENV_CLIENT_SECRET: &str = "SPOTIFY_CLI_CLIENT_SECRET";
+pub const ENV_REFRESH_TOKEN: &str = "SPOTIFY_CLI_REFRESH_TOKEN";
+pub const ENV_ACCESS_TOKEN: &str = "SPOTIFY_CLI_ACCESS_TOKEN";
+pub const ENV_API_URL: &str = "SPOTIFY_CLI_API_URL";
+pub const ENV_ACCOUNTS_URL: &str = "SPOTIFY_CLI_ACCOUNTS_URL";
+
+/// The selected profile with any `SPOTIFY_CLI_*` variables layered on top.
+///
+/// Each variable beats its counterpart in the file, and an access token from the
+/// environment is used as is, without refreshing or touching the token cache.
+#[derive(Debug)]
+pub struct ResolvedConfig {
+    pub config: Config,
+    pub access_token: Option<String>,
+    pub client_id: Source,
+    pub client_secret: Source,
+    pub refresh_token: Source,
+    pub access_token_source: Source,
+    pub api_url: Source,
+    pub accounts_url: Source,
+}
+
+impl ResolvedConfig {
+    fn resolve(file: Config, env: impl Fn(&str) -> Option<String>) -> Self {
+        fn pick(env: Option<String>, file: Option<String>) -> (Option<String>, Source) {
+            match (env, file) {
+                (Some(v), _) => (Some(v), Source::Env),
+                (None, Some(v)) => (Some(v), Source::File),
+                (None, None) => (None, Source::Unset),
+            }
+        }
+
+        let mut config = file;
+        let (client_id, client_id_source) = pick(env(ENV_CLIENT_ID), config.client_id.take());
+        let (client_secret, client_secret_source) = pick(env(ENV_CLIENT_SECRET), config.client_secret.take());
+        let (refresh_token, refresh_token_source) = pick(env(ENV_REFRESH_TOKEN), config.refresh_token.take());
+        let (access_token, access_token_source) = pick(env(ENV_ACCESS_TOKEN), None);
+        let (api_url, api_url_source) = pick(env(ENV_API_URL), config.api_url.take());
+        let (accounts_url, accounts_url_source) = pick(env(ENV_ACCOUNTS_URL), config.accounts_url.take());
+        config.client_id = client_id;
+        config.client_secret = client_secret;
+        config.refresh_token = refresh_token;
+        config.api_url = api_url;
+        config.accounts_url = accounts_url;
+
+        ResolvedConfig {
+            config,
+            access_token,
+            client_id: client_id_source,
+            client_secret: client_secret_source,
+            refresh_token: refresh_token_source,
+            access_token_source,
+            api_url: api_url_source,
+            accounts_url: accounts_url_source,
+        }
+    }
+}
+
+pub fn read_resolved_config(path: &ConfigPath) -> Result<ResolvedConfig, ConfigError> {
+    Ok(ResolvedConfig::resolve(read_config(path)?, |name| {
+        std::env::var(name).ok().filter(|v| !v.is_empty())
+    }))
+}
+
+/// Access tokens live in here between runs, so we aren't refreshing on every invocation
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TokenCache {
+    pub access_token: Option<String>,
+    /// Seconds since the unix epoch
+    pub expires_at: Option<u64>,
+    /// Newer than the one in the config, if Spotify rotated it
+    pub refresh_token: Option<String>,
+    /// Came from the client credentials grant, so it's no good for user commands
+    #[serde(default)]
+    pub app_only: bool,
+}
+
+impl TokenCache {
+    /// Don't hand out tokens that will die mid-command
+    const EXPIRY_MARGIN: u64 = 60;
+
+    pub fn new(access_token: String, expires_in: u64, refresh_token: Option<String>) -> Self {
+        TokenCache {
+            access_token: Some(access_token),
+            expires_at: Some(now() + expires_in),
+            refresh_token,
+            app_only: false,
+        }
+    }
+
+    pub fn valid_access_token(&self, now: u64) -> Option<&str> {
+        match (&self.access_token, self.expires_at) {
+            (Some(token), Some(expires_at)) if now + Self::EXPIRY_MARGIN < expires_at => Some(token),
+            _ => None,
+        }
+    }
+}
+
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// Set once from --verbose, for the bits of output only wanted while debugging
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn config_file_path(path: &ConfigPath) -> PathBuf {
+    path.file.clone()
 }
 
-pub fn read_config(path: &PathBuf) -> Config {
-    let path = PathBuf::from(
-        shellexpand::tilde(&path.to_path_buf().into_os_string().into_string().ok().unwrap()).to_string(),
-    ).join("config");
+/// The raw text of the config file, None if there isn't one yet
+pub fn read_config_text(path: &ConfigPath) -> Result<Option<String>, ConfigError> {
+    let path = config_file_path(path);
 
     if !path.is_file() {
-        Config {
-            refresh_token: None,
-            client_id: None,
-            client_secret: None,
+        return Ok(None);
+    }
+    fs::read_to_string(&path)
+        .map(Some)
+        .map_err(|source| ConfigError::Read { path, source })
+}
+
+pub fn read_config_file(path: &ConfigPath) -> Result<ConfigFile, ConfigError> {
+    let data = match read_config_text(path)? {
+        Some(data) => data,
+        None => return Ok(ConfigFile::default()),
+    };
+    let path = config_file_path(path);
+
+    let mut file: ConfigFile =
+        toml::from_str(&data).map_err(|e| ConfigError::parse(path.clone(), &data, e))?;
+    if let Some(salt) = file.encryption_salt.clone() {
+        for secret in file.secrets_mut() {
+            open_value(&salt, secret).map_err(|e| ConfigError::Encryption {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+        }
+    }
+    Ok(file)
+}
+
+/// The selected profile's config, empty if the profile doesn't exist yet
+pub fn read_config(path: &ConfigPath) -> Result<Config, ConfigError> {
+    let file = read_config_file(path)?;
+    Ok(file.get(file.profile_name(path)).cloned().unwrap_or_default())
+}
+
+pub fn write_config_file(path: &ConfigPath, file: &ConfigFile) -> Result<(), ConfigError> {
+    let path = config_file_path(path);
+
+    let mut file = file.clone();
+    if let Some(salt) = file.encryption_salt.clone() {
+        for secret in file.secrets_mut() {
+            seal_value(&salt, secret).map_err(|e| ConfigError::Encryption {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
         }
-    } else {
-        let data = fs::read_to_string(path).expect("Failed to read file!");
-        toml::from_str(&data).expect("Failed to parse toml")
     }
+    let data: String = toml::to_string(&file).expect("Config should always serialize");
+
+    write_private(&path, data.as_bytes()).map_err(|source| ConfigError::Write { path, source })
 }
 
-pub fn write_config(path: PathBuf, config: Config) {
-    let path = PathBuf::from(
-        shellexpand::tilde(&path.clone().into_os_string().into_string().ok().unwrap()).to_string(),
-    ).join("config");
+#[cfg(feature = "encrypted_config")]
+fn open_value(salt: &str, value: &mut String) -> Result<(), crate::crypt::CryptError> {
+    if crate::crypt::is_sealed(value) {
+        *value = crate::crypt::key_for(salt, false)?.open(value)?;
+    }
+    Ok(())
+}
 
-    let data: String = toml::to_string(&config).unwrap();
+#[cfg(feature = "encrypted_config")]
+fn seal_value(salt: &str, value: &mut String) -> Result<(), crate::crypt::CryptError> {
+    if !crate::crypt::is_sealed(value) {
+        *value = crate::crypt::key_for(salt, false)?.seal(value);
+    }
+    Ok(())
+}
 
-    fs::write(path, data).ok();
+#[cfg(not(feature = "encrypted_config"))]
+fn open_value(_salt: &str, _value: &mut String) -> Result<(), &'static str> {
+    Err("Config is encrypted, rebuild with --features encrypted_config to read it")
 }
 
-pub fn get_token(path: &PathBuf) -> crate::spotify::types::Token {
-    // Token acquisition
-    let config = crate::util::read_config(&path);
-    let token = crate::commands::auth::refresh(&config);
-    if token.is_err() {
-        println!("Failed to auth: {:?}", token.unwrap_err());
-        std::process::exit(1);
+#[cfg(not(feature = "encrypted_config"))]
+fn seal_value(_salt: &str, _value: &mut String) -> Result<(), &'static str> {
+    Err("Config is encrypted, rebuild with --features encrypted_config to write it")
+}
+
+/// Replaces the selected profile's config, leaving other profiles alone
+pub fn write_config(path: &ConfigPath, config: Config) -> Result<(), ConfigError> {
+    let mut file = read_config_file(path)?;
+    let name = file.profile_name(path).to_owned();
+    file.set(&name, config);
+    write_config_file(path, &file)
+}
+
+fn token_cache_path(path: &ConfigPath, profile: &str) -> PathBuf {
+    match profile {
+        DEFAULT_PROFILE => path.token_cache.clone(),
+        profile => {
+            let mut name = path.token_cache.clone().into_os_string();
+            name.push(".");
+            name.push(profile);
+            name.into()
+        }
+    }
+}
+
+fn selected_token_cache_path(path: &ConfigPath, file: &ConfigFile) -> PathBuf {
+    token_cache_path(path, file.profile_name(path))
+}
+
+pub fn read_token_cache(path: &ConfigPath) -> TokenCache {
+    // If the config can't be read, neither can anything cached for it
+    let file = match read_config_file(path) {
+        Ok(file) => file,
+        Err(_) => return TokenCache::default(),
+    };
+    let mut cache = read_token_cache_at(&selected_token_cache_path(path, &file));
+    // Rotated refresh tokens are as good as the one in the config, so they get the same treatment
+    if let (Some(salt), Some(refresh_token)) = (file.encryption_salt, cache.refresh_token.as_mut()) {
+        if open_value(&salt, refresh_token).is_err() {
+            return TokenCache::default();
+        }
+    }
+    cache
+}
+
+fn read_token_cache_at(path: &Path) -> TokenCache {
+    // A missing or mangled cache just means we refresh, no need to complain
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|data| toml::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn write_token_cache(path: &ConfigPath, cache: &TokenCache) -> Result<(), ConfigError> {
+    let file = read_config_file(path)?;
+    let mut cache = cache.clone();
+    let cache_path = selected_token_cache_path(path, &file);
+    if let (Some(salt), Some(refresh_token)) = (&file.encryption_salt, cache.refresh_token.as_mut()) {
+        // Refresh tokens rotate, so this one may be the only login left. It mustn't be written
+        // out in the clear, but dropping it quietly would log the user out, so say so instead.
+        seal_value(salt, refresh_token).map_err(|e| ConfigError::Encryption {
+            path: cache_path.clone(),
+            message: format!("can't encrypt the refresh token, so the token cache wasn't written: {}", e),
+        })?;
+    }
+    let data = toml::to_string(&cache).expect("Token cache should always serialize");
+    write_private(&cache_path, data.as_bytes()).map_err(|source| ConfigError::Write {
+        path: cache_path,
+        source,
+    })
+}
+
+pub fn clear_token_cache(path: &ConfigPath) {
+    if let Ok(file) = read_config_file(path) {
+        fs::remove_file(selected_token_cache_path(path, &file)).ok();
+    }
+}
+
+/// Writes to a temporary file only we can read, then moves it into place,
+/// so a crash can't leave a half-written file behind
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        crate::dirs::create_private_dir(dir)?;
+    }
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or("file".as_ref()));
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// A client with a freshly refreshed access token, exiting if the config can't provide one
+/// or if it is known to lack any of `scopes`
+pub fn get_client(path: &ConfigPath, scopes: &[&str]) -> SpotifyClient {
+    let resolved = read_resolved_config(path).unwrap_or_else(|e| e.exit());
+    let config = resolved.config.clone();
+    #[cfg(feature = "cassettes")]
+    if let Some(client) = replay_client(&config) {
+        return client;
+    }
+    let refresh = refresher(path, &resolved);
+    user_client(SpotifyClient::new(get_token(path, resolved, scopes), &config), refresh)
+}
+
+/// Replaying needs neither a login nor granted scopes, nothing goes over the wire
+#[cfg(feature = "cassettes")]
+fn replay_client(config: &Config) -> Option<SpotifyClient> {
+    use crate::spotify::cassette;
+    cassette::replaying().then(|| SpotifyClient::new(Token::user(cassette::REDACTED.to_owned()), config))
+}
+
+fn user_client(client: SpotifyClient, refresh: Option<Refresh>) -> SpotifyClient {
+    match refresh {
+        Some(refresh) => client.with_refresh(refresh),
+        None => client,
+    }
+}
+
+/// How to replace a user token that expires mid-command. An access token from the
+/// environment is used as is, so there's nothing to be done about that one.
+fn refresher(path: &ConfigPath, resolved: &ResolvedConfig) -> Option<Refresh> {
+    if resolved.access_token.is_some() {
+        return None;
+    }
+
+    let config = resolved.config.clone();
+    if resolved.refresh_token == Source::Env {
+        return Some(Box::new(move || {
+            let res = crate::commands::auth::refresh(&config)?;
+            Ok(Token::user(res.access_token))
+        }));
+    }
+    let path = path.clone();
+    Some(Box::new(move || refresh_token(&path, config.clone())))
+}
+
+fn get_token(path: &ConfigPath, resolved: ResolvedConfig, scopes: &[&str]) -> Token {
+    let missing = resolved.config.missing_scopes(scopes);
+    if !missing.is_empty() {
+        eprintln!(
+            "This command needs scope(s) that weren't granted: {}\nRun auth --force{} to add them.",
+            missing.join(", "),
+            missing.iter().map(|s| format!(" --scope {}", s)).collect::<String>()
+        );
+        std::process::exit(crate::spotify::error::EXIT_PERMISSION);
+    }
+
+    resolve_token(path, resolved).unwrap_or_else(|e| e.exit("Failed to auth"))
+}
+
+/// For commands that only read catalog data: a user token if there's a login,
+/// otherwise an app-only token from the client credentials grant
+pub fn get_read_client(path: &ConfigPath) -> SpotifyClient {
+    let resolved = read_resolved_config(path).unwrap_or_else(|e| e.exit());
+    let config = resolved.config.clone();
+    #[cfg(feature = "cassettes")]
+    if let Some(client) = replay_client(&config) {
+        return client;
+    }
+    if resolved.access_token.is_some() || resolved.config.refresh_token.is_some() {
+        let refresh = refresher(path, &resolved);
+        return user_client(SpotifyClient::new(get_token(path, resolved, &[]), &config), refresh);
+    }
+
+    let token = fetch_app_token(path, resolved).unwrap_or_else(|e| e.exit("Failed to get an app token"));
+    SpotifyClient::new(token, &config)
+}
+
+fn fetch_app_token(
+    path: &ConfigPath,
+    resolved: ResolvedConfig,
+) -> Result<Token, crate::spotify::Error> {
+    // Only cache what the file's credentials got us, same as user tokens
+    let use_cache = resolved.client_id == Source::File && resolved.client_secret == Source::File;
+    if use_cache {
+        let cache = read_token_cache(path);
+        if let Some(token) = cache.valid_access_token(now()).filter(|_| cache.app_only) {
+            return Ok(Token::app(token.to_owned()));
+        }
+    }
+
+    let res = crate::commands::auth::client_credentials(&resolved.config)?;
+    if use_cache {
+        let mut cache = TokenCache::new(res.access_token.clone(), res.expires_in, None);
+        cache.app_only = true;
+        if let Err(e) = write_token_cache(path, &cache) {
+            eprintln!("Failed to write token cache: {}", e);
+        }
+    }
+
+    Ok(Token::app(res.access_token))
+}
+
+/// Picks the access token according to the precedence on `ResolvedConfig`
+pub fn resolve_token(
+    path: &ConfigPath,
+    resolved: ResolvedConfig,
+) -> Result<Token, crate::spotify::Error> {
+    if let Some(token) = resolved.access_token {
+        return Ok(Token::user(token));
+    }
+
+    // A refresh token from the environment has nothing to do with whatever is cached,
+    // and there may not even be a config directory to cache into
+    if resolved.refresh_token == Source::Env {
+        let res = crate::commands::auth::refresh(&resolved.config)?;
+        return Ok(Token::user(res.access_token));
+    }
+
+    fetch_token(path, resolved.config)
+}
+
+/// Hands out the cached access token if it's still good, refreshing it otherwise
+pub fn fetch_token(
+    path: &ConfigPath,
+    config: Config,
+) -> Result<Token, crate::spotify::Error> {
+    let cache = read_token_cache(path);
+    if let Some(token) = cache.valid_access_token(now()).filter(|_| !cache.app_only) {
+        return Ok(Token::user(token.to_owned()));
+    }
+
+    refresh_token(path, config)
+}
+
+/// Refreshes the access token whatever the cache says, and caches the new one
+fn refresh_token(
+    path: &ConfigPath,
+    mut config: Config,
+) -> Result<Token, crate::spotify::Error> {
+    let cache = read_token_cache(path);
+    // A rotated refresh token in the cache supersedes the one in the config
+    if cache.refresh_token.is_some() {
+        config.refresh_token = cache.refresh_token.clone();
+    }
+    let res = crate::commands::auth::refresh(&config)?;
+
+    // If we were handed a new refresh token the old one is dead, so hold on to it
+    let refresh_token = res.refresh_token.or(cache.refresh_token.filter(|_| !cache.app_only));
+    let cache = TokenCache::new(res.access_token.clone(), res.expires_in, refresh_token);
+    if let Err(e) = write_token_cache(path, &cache) {
+        eprintln!("Failed to write token cache: {}", e);
+    }
+
+    Ok(Token::user(res.access_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_cache_expiry() {
+        let cache = TokenCache {
+            access_token: Some("token".to_owned()),
+            expires_at: Some(1000),
+            refresh_token: None,
+            app_only: false,
+        };
+        assert_eq!(cache.valid_access_token(0), Some("token"));
+        assert_eq!(cache.valid_access_token(939), Some("token"));
+        assert_eq!(cache.valid_access_token(940), None);
+        assert_eq!(cache.valid_access_token(2000), None);
+        assert_eq!(TokenCache::default().valid_access_token(0), None);
+    }
+
+    #[test]
+    fn test_config_profiles() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+            client_id = "legacy"
+            default_profile = "office"
+
+            [profiles.office]
+            client_id = "office-id"
+            refresh_token = "office-token"
+            "#,
+        )
+        .unwrap();
+
+        let selected = ConfigPath { file: PathBuf::new(), token_cache: PathBuf::new(), profile: None };
+        assert_eq!(file.profile_name(&selected), "office");
+        assert_eq!(file.get("office").unwrap().client_id.as_deref(), Some("office-id"));
+        assert_eq!(file.get(DEFAULT_PROFILE).unwrap().client_id.as_deref(), Some("legacy"));
+        assert!(file.get("missing").is_none());
+
+        let selected = ConfigPath { file: PathBuf::new(), token_cache: PathBuf::new(), profile: Some("default".to_owned()) };
+        assert_eq!(file.profile_name(&selected), DEFAULT_PROFILE);
+
+        let names: Vec<&str> = file.profiles().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["default", "office"]);
+
+        // Round trips without losing either profile
+        let file: ConfigFile = toml::from_str(&toml::to_string(&file).unwrap()).unwrap();
+        assert_eq!(file.get("office").unwrap().refresh_token.as_deref(), Some("office-token"));
+        assert_eq!(file.get(DEFAULT_PROFILE).unwrap().client_id.as_deref(), Some("legacy"));
+    }
+
+    #[test]
+    fn test_resolved_config() {
+        let file = Config {
+            client_id: Some("file-id".to_owned()),
+            client_secret: Some("file-secret".to_owned()),
+            ..Default::default()
+        };
+        let resolved = ResolvedConfig::resolve(file, |name| match name {
+            ENV_CLIENT_ID => Some("env-id".to_owned()),
+            ENV_REFRESH_TOKEN => Some("env-token".to_owned()),
+            ENV_API_URL => Some("http://127.0.0.1:9999/v1".to_owned()),
+            ENV_ACCOUNTS_URL => Some("http://127.0.0.1:9999".to_owned()),
+            _ => None,
+        });
+
+        assert_eq!(resolved.config.client_id.as_deref(), Some("env-id"));
+        assert_eq!(resolved.client_id, Source::Env);
+        assert_eq!(resolved.config.client_secret.as_deref(), Some("file-secret"));
+        assert_eq!(resolved.client_secret, Source::File);
+        assert_eq!(resolved.config.refresh_token.as_deref(), Some("env-token"));
+        assert_eq!(resolved.refresh_token, Source::Env);
+        assert_eq!(resolved.access_token, None);
+        assert_eq!(resolved.access_token_source, Source::Unset);
+        assert_eq!(resolved.config.api_url.as_deref(), Some("http://127.0.0.1:9999/v1"));
+        assert_eq!(resolved.api_url, Source::Env);
+        assert_eq!(resolved.config.accounts_url.as_deref(), Some("http://127.0.0.1:9999"));
+    }
+
+    #[test]
+    fn test_token_cache_write() {
+        // Missing directories get created on the way
+        let root = std::env::temp_dir().join(format!("spotify-cli-test-{}", std::process::id()));
+        let dir = root.join("state");
+        let dir = ConfigPath {
+            file: root.join("config"),
+            token_cache: dir.join("token_cache"),
+            profile: None,
+        };
+
+        let cache = TokenCache::new("token".to_owned(), 3600, Some("refresh".to_owned()));
+        write_token_cache(&dir, &cache).unwrap();
+        let read = read_token_cache(&dir);
+        assert_eq!(read.access_token.as_deref(), Some("token"));
+        assert_eq!(read.refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(read.expires_at, cache.expires_at);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&dir.token_cache).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            let mode = fs::metadata(root.join("state")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        assert!(!root.join("state/.token_cache.tmp").exists());
+
+        // An encrypted config we can't encrypt for keeps the old cache rather than dropping the token
+        #[cfg(not(feature = "encrypted_config"))]
+        {
+            fs::write(&dir.file, "encryption_salt = \"salt\"\n").unwrap();
+            let rotated = TokenCache::new("new".to_owned(), 3600, Some("rotated".to_owned()));
+            assert!(matches!(write_token_cache(&dir, &rotated), Err(ConfigError::Encryption { .. })));
+            assert!(fs::read_to_string(&dir.token_cache).unwrap().contains("refresh"));
+            assert!(!fs::read_to_string(&dir.token_cache).unwrap().contains("rotated"));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
     }
-    token.unwrap()
 }